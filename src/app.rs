@@ -8,9 +8,12 @@
 /// - a request to quit broot
 /// - a request to launch an executable (thus leaving broot)
 use std::io::{self, stdin, Write};
+use std::path::PathBuf;
 use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::{mpsc, Arc};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
 use std::thread;
+use std::time::Duration;
 use termion::input::TermRead;
 
 use crate::app_context::AppContext;
@@ -22,6 +25,11 @@ use crate::screens::Screen;
 use crate::spinner::Spinner;
 use crate::status::Status;
 use crate::task_sync::TaskLifetime;
+use crate::watcher::Watcher;
+
+// how often, while waiting for keys, we check whether the watched
+//  directories changed
+const WATCH_PERIOD: Duration = Duration::from_millis(200);
 
 pub enum AppStateCmdResult {
     Quit,
@@ -30,6 +38,7 @@ pub enum AppStateCmdResult {
     DisplayError(String),
     NewState(Box<dyn AppState>),
     PopState,
+    RefreshState,
 }
 
 impl AppStateCmdResult {
@@ -51,6 +60,8 @@ pub trait AppState {
     fn display(&mut self, screen: &mut Screen, con: &AppContext) -> io::Result<()>;
     fn write_status(&self, screen: &mut Screen, cmd: &Command, con: &AppContext) -> io::Result<()>;
     fn write_flags(&self, screen: &mut Screen, con: &AppContext) -> io::Result<()>;
+    fn watched_dirs(&self) -> Vec<PathBuf>; // the dirs whose changes should trigger a refresh
+    fn refresh(&mut self); // reload the content, possibly as a pending task
 }
 
 pub struct App {
@@ -83,6 +94,38 @@ impl App {
         }
     }
 
+    // wait for the next key. If the watched directories change in
+    //  the meantime, the state is refreshed and None is returned.
+    fn wait_key<T>(
+        &mut self,
+        rx_keys: &mpsc::Receiver<T>,
+        watcher: &mut Option<Watcher>,
+    ) -> Result<Option<T>, RecvTimeoutError> {
+        let watcher = match watcher {
+            Some(watcher) => watcher,
+            None => {
+                return Ok(Some(rx_keys.recv()?));
+            }
+        };
+        loop {
+            match rx_keys.recv_timeout(WATCH_PERIOD) {
+                Ok(c) => {
+                    return Ok(Some(c));
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    if watcher.has_changes() {
+                        debug!("watched directories changed");
+                        self.mut_state().refresh();
+                        return Ok(None);
+                    }
+                }
+                Err(e) => {
+                    return Err(e);
+                }
+            }
+        }
+    }
+
     pub fn run(mut self, con: &AppContext) -> io::Result<Option<Launchable>> {
         let (w, h) = termion::terminal_size()?;
         let mut screen = Screen::new(w, h)?;
@@ -116,6 +159,13 @@ impl App {
                 }
             }
         });
+        let mut watcher = match Watcher::new() {
+            Ok(watcher) => Some(watcher),
+            Err(e) => {
+                warn!("directories won't be watched : {:?}", e);
+                None
+            }
+        };
         let mut cmd = Command::new();
         screen.write_input(&cmd)?;
         screen
@@ -140,8 +190,16 @@ impl App {
                 screen.write_spinner(false)?;
             }
             self.mut_state().display(&mut screen, con)?;
-            let c = match rx_keys.recv() {
-                Ok(c) => c,
+            if let Some(watcher) = &mut watcher {
+                watcher.watch(self.state().watched_dirs());
+            }
+            let c = match self.wait_key(&rx_keys, &mut watcher) {
+                Ok(Some(c)) => c,
+                Ok(None) => {
+                    // the directories changed, the state will be refreshed
+                    self.state().write_status(&mut screen, &cmd, con)?;
+                    continue;
+                }
                 Err(_) => {
                     break;
                 }
@@ -174,6 +232,11 @@ impl App {
                         self.state().write_status(&mut screen, &cmd, con)?;
                     }
                 }
+                AppStateCmdResult::RefreshState => {
+                    self.mut_state().refresh();
+                    cmd = cmd.pop_verb();
+                    self.state().write_status(&mut screen, &cmd, con)?;
+                }
                 AppStateCmdResult::DisplayError(txt) => {
                     screen.write_status_err(&txt)?;
                 }
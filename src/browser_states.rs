@@ -1,17 +1,18 @@
 //! an application state dedicated to displaying a tree
 
+use std::collections::HashSet;
 use std::io::{self, Write};
-use std::path::PathBuf;
-use std::time::Instant;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 use termion::color;
 
 use crate::app::{AppState, AppStateCmdResult};
 use crate::app_context::AppContext;
 use crate::commands::{Action, Command};
+use crate::composite_patterns::CompositePattern;
 use crate::external::Launchable;
 use crate::flat_tree::Tree;
 use crate::help_states::HelpState;
-use crate::patterns::Pattern;
 use crate::screens::{self, Screen};
 use crate::status::Status;
 use crate::task_sync::TaskLifetime;
@@ -20,37 +21,285 @@ use crate::tree_options::{OptionBool, TreeOptions};
 use crate::tree_views::TreeView;
 use crate::verbs::VerbExecutor;
 
+// the duration of a search step, after which the best matches found
+//  so far are displayed while the search goes on
+const SEARCH_STEP_DURATION: Duration = Duration::from_millis(100);
+
 pub struct BrowserState {
     pub tree: Tree,
     pub filtered_tree: Option<Tree>,
-    pending_pattern: Option<Pattern>, // a pattern which has not yet be applied
+    tree_builder: Option<TreeBuilder>, // kept to go on with the exploration when scrolling
+    pending_pattern: Option<CompositePattern>, // a pattern which has not yet be applied
+    pending_search: Option<TreeBuilder>, // a search whose results are still being gathered
+    pending_rebuild: Option<TreeBuilder>, // a rebuild of the tree, done in the background
+    selection_moved: bool,             // true when the user moved the selection during the search
+    total_search: bool,                // true when searches explore the whole tree
+    expanded_dirs: HashSet<PathBuf>,   // directories whose children are all listed
 }
 
 impl BrowserState {
     pub fn new(path: PathBuf, mut options: TreeOptions, tl: &TaskLifetime) -> Option<BrowserState> {
         let pending_pattern = options.pattern;
         options.pattern = None;
-        let builder = TreeBuilder::from(path, options, screens::max_tree_height() as usize);
-        match builder.build(tl) {
+        let mut builder = TreeBuilder::from(path, options, screens::max_tree_height() as usize);
+        match builder.resumable_build(tl) {
             Some(tree) => Some(BrowserState {
                 tree,
                 filtered_tree: None,
+                tree_builder: Some(builder),
                 pending_pattern,
+                pending_search: None,
+                pending_rebuild: None,
+                selection_moved: false,
+                total_search: false,
+                expanded_dirs: HashSet::new(),
             }),
             None => None, // interrupted
         }
     }
+    // a state whose root is the parent of the given path, which is
+    //  selected (all the children of the root are listed if needed)
+    pub fn new_selecting(
+        path: &Path,
+        options: TreeOptions,
+        tl: &TaskLifetime,
+    ) -> Option<BrowserState> {
+        let root = path.parent()?.to_path_buf();
+        let mut state = BrowserState::new(root.clone(), options, tl)?;
+        if !state.tree.try_select_path(path) {
+            state.expanded_dirs.insert(root);
+            state.rebuild(tl);
+            state.tree.try_select_path(path);
+        }
+        state
+            .tree
+            .make_selection_visible(screens::max_tree_height() as i32);
+        Some(state)
+    }
+    // a state whose searches explore the whole tree, whatever the time
+    //  it takes, and count the matches
+    pub fn new_total_search(
+        path: PathBuf,
+        options: TreeOptions,
+        tl: &TaskLifetime,
+    ) -> Option<BrowserState> {
+        let mut state = BrowserState::new(path, options, tl)?;
+        state.total_search = true;
+        Some(state)
+    }
+    // a state displaying the same root with other options. The current
+    //  search goes on, and the selected path stays selected if it's still
+    //  displayed (or else its closest displayed ancestor)
+    pub fn with_options(
+        &self,
+        mut options: TreeOptions,
+        tl: &TaskLifetime,
+    ) -> Option<BrowserState> {
+        options.pattern = match self.current_pattern() {
+            // the pattern is rebuilt as it may depend on the options (or
+            //  have been made tolerant to typos by the builder)
+            Some(pattern) => match CompositePattern::from(&pattern.to_expr(), &options) {
+                Ok(pattern) => Some(pattern),
+                Err(e) => {
+                    warn!("pattern can't be rebuilt : {:?}", e);
+                    None
+                }
+            },
+            None => None,
+        };
+        let mut state = BrowserState::new(self.tree.root().clone(), options, tl)?;
+        state.total_search = self.total_search;
+        if !self.expanded_dirs.is_empty() {
+            state.expanded_dirs = self.expanded_dirs.clone();
+            state.rebuild(tl);
+        }
+        let displayed_tree = self.displayed_tree();
+        state
+            .tree
+            .select_path_or_ancestor(&displayed_tree.selected_line().path);
+        state.tree.scroll = displayed_tree.scroll;
+        state
+            .tree
+            .make_selection_visible(screens::max_tree_height() as i32);
+        state.selection_moved = true; // so that the search keeps the selection
+        Some(state)
+    }
+    // the pattern of the search being done or displayed, if any
+    fn current_pattern(&self) -> Option<&CompositePattern> {
+        if let Some(pattern) = &self.pending_pattern {
+            return Some(pattern);
+        }
+        if let Some(builder) = &self.pending_search {
+            return builder.pattern();
+        }
+        match &self.filtered_tree {
+            Some(tree) => tree.options.pattern.as_ref(),
+            None => None,
+        }
+    }
     pub fn displayed_tree(&self) -> &Tree {
         match &self.filtered_tree {
             Some(tree) => &tree,
             None => &self.tree,
         }
     }
+    // rebuild the tree, keeping the selection and the scroll, for
+    //  example after a change in the displayed directories. The
+    //  current search, if any, is done again.
+    fn rebuild(&mut self, tl: &TaskLifetime) {
+        self.start_rebuild();
+        self.go_on_rebuilding(tl);
+    }
+    // prepare a rebuild of the tree, which is then done as a pending
+    //  task (a previous unfinished rebuild is dropped)
+    fn start_rebuild(&mut self) {
+        // as many lines as before, as more may have been loaded while scrolling
+        let mut builder = TreeBuilder::from(
+            self.tree.root().clone(),
+            self.tree.options.without_pattern(),
+            self.tree
+                .lines
+                .len()
+                .max(screens::max_tree_height() as usize),
+        );
+        builder.expand(&self.expanded_dirs);
+        self.pending_rebuild = Some(builder);
+    }
+    // go on with the pending rebuild, which is resumed later when interrupted
+    fn go_on_rebuilding(&mut self, tl: &TaskLifetime) {
+        let builder = match &mut self.pending_rebuild {
+            Some(builder) => builder,
+            None => {
+                return;
+            }
+        };
+        let mut tree = match builder.resumable_build(tl) {
+            Some(tree) => tree,
+            None => {
+                return; // interrupted
+            }
+        };
+        // forget the expanded directories which aren't displayed anymore
+        let filtered_tree = &self.filtered_tree;
+        self.expanded_dirs.retain(|dir| {
+            tree.has_dir(dir) || filtered_tree.as_ref().is_some_and(|t| t.has_dir(dir))
+        });
+        // the selected file may have been deleted
+        tree.select_path_or_ancestor(&self.tree.selected_line().path);
+        tree.scroll = self.tree.scroll;
+        tree.make_selection_visible(screens::max_tree_height() as i32);
+        self.tree = tree;
+        self.tree_builder = self.pending_rebuild.take();
+        if self.pending_pattern.is_some() || self.pending_search.is_some() {
+            return; // the search started since then already sees the changes
+        }
+        if let Some(filtered_tree) = &self.filtered_tree {
+            if let Some(pattern) = &filtered_tree.options.pattern {
+                // the pattern may have been made tolerant to typos by the builder
+                let options = &filtered_tree.options;
+                if let Ok(pattern) = CompositePattern::from(&pattern.to_expr(), options) {
+                    self.pending_pattern = Some(pattern);
+                    self.selection_moved = true; // so that the selection is kept
+                }
+            }
+        }
+    }
+    // when the unfiltered tree is scrolled near its bottom, go on with
+    //  the exploration and make a bigger tree, the selected line staying
+    //  at the same place on screen
+    fn load_more_lines_if_needed(&mut self, page_height: i32) {
+        if self.filtered_tree.is_some()
+            || self.tree.scroll + 2 * page_height < self.tree.lines.len() as i32
+        {
+            return;
+        }
+        let builder = match &mut self.tree_builder {
+            Some(builder) if !builder.is_exhausted() => builder,
+            _ => {
+                return;
+            }
+        };
+        builder.extend(page_height as usize);
+        let mut tree = match builder.resumable_build(&TaskLifetime::unlimited()) {
+            Some(tree) => tree,
+            None => {
+                return; // interrupted
+            }
+        };
+        debug!(
+            "tree extended from {} to {} lines",
+            self.tree.lines.len(),
+            tree.lines.len()
+        );
+        tree.select_path_or_ancestor(&self.tree.selected_line().path);
+        let dy = self.tree.selection as i32 - self.tree.scroll;
+        tree.scroll = (tree.selection as i32 - dy).max(0);
+        self.tree = tree;
+    }
+    // list all the children of the selected directory (or of the parent
+    //  of the selected pruning line), in place. Return false when there's
+    //  nothing to expand
+    pub fn expand_selection(&mut self) -> bool {
+        let line = self.displayed_tree().selected_line();
+        let dir = if line.is_pruning() {
+            line.path.parent()
+        } else if line.is_dir() && line.unlisted > 0 {
+            Some(line.path.as_path())
+        } else {
+            None
+        };
+        let dir = match dir {
+            Some(dir) => dir.to_path_buf(),
+            None => {
+                return false;
+            }
+        };
+        debug!("expanding {:?}", &dir);
+        self.expanded_dirs.insert(dir);
+        self.rebuild(&TaskLifetime::unlimited());
+        true
+    }
+    // replace the filtered tree with a new result of the pending search.
+    // If the user moved the selection (or the search is done again after
+    //  a refresh), the selected file stays selected and the scroll is kept
+    fn show_search_result(&mut self, mut tree: Tree) {
+        let selected_path = match self.selection_moved {
+            true => Some(self.displayed_tree().selected_line().path.clone()),
+            false => None,
+        };
+        let reselected = match selected_path {
+            Some(path) => tree.try_select_path(&path),
+            None => false,
+        };
+        let (_, page_height) = termion::terminal_size().unwrap();
+        let mut page_height = page_height as i32;
+        page_height -= 2;
+        if reselected {
+            let max_scroll = (tree.lines.len() as i32 - page_height).max(0);
+            tree.scroll = self.displayed_tree().scroll.min(max_scroll);
+        } else {
+            tree.try_select_best_match();
+        }
+        tree.make_selection_visible(page_height);
+        self.filtered_tree = Some(tree);
+    }
 }
 
 impl AppState for BrowserState {
     fn apply(&mut self, cmd: &mut Command, con: &AppContext) -> io::Result<AppStateCmdResult> {
-        self.pending_pattern = None;
+        match &cmd.action {
+            Action::MoveSelection(_) | Action::ScrollPage(_) | Action::Next => {
+                // the search goes on, keeping the selection
+                self.selection_moved = true;
+            }
+            Action::VerbEdit(_) | Action::Verb(_) => {
+                // the search goes on, verbs may need its pattern
+            }
+            _ => {
+                self.pending_pattern = None;
+                self.pending_search = None;
+            }
+        }
         let (_, page_height) = termion::terminal_size().unwrap();
         let mut page_height = page_height as i32;
         page_height -= 2;
@@ -69,6 +318,9 @@ impl AppState for BrowserState {
                 }
             }
             Action::MoveSelection(dy) => {
+                if *dy > 0 {
+                    self.load_more_lines_if_needed(page_height);
+                }
                 match self.filtered_tree {
                     Some(ref mut tree) => {
                         tree.move_selection(*dy, page_height);
@@ -80,6 +332,9 @@ impl AppState for BrowserState {
                 AppStateCmdResult::Keep
             }
             Action::ScrollPage(dp) => {
+                if *dp > 0 {
+                    self.load_more_lines_if_needed(page_height);
+                }
                 // this should not be computed here
                 if page_height < self.displayed_tree().lines.len() as i32 {
                     let dy = dp * page_height;
@@ -101,6 +356,11 @@ impl AppState for BrowserState {
                 };
                 if tree.selection == 0 {
                     AppStateCmdResult::Quit
+                } else if tree.selected_line().is_pruning() {
+                    self.expand_selection();
+                    AppStateCmdResult::Keep
+                } else if let Some(kind) = tree.selected_line().special_kind() {
+                    AppStateCmdResult::DisplayError(format!("a {} can't be opened", kind))
                 } else if tree.selected_line().is_dir() {
                     let tl = TaskLifetime::unlimited();
                     AppStateCmdResult::from_optional_state(BrowserState::new(
@@ -117,15 +377,13 @@ impl AppState for BrowserState {
                 Some(verb) => self.execute_verb(verb, con)?,
                 None => AppStateCmdResult::verb_not_found(&verb_key),
             },
-            Action::PatternEdit(pat) => match pat.len() {
-                0 => {
-                    self.filtered_tree = None;
-                    AppStateCmdResult::Keep
-                }
-                _ => {
-                    self.pending_pattern = Some(Pattern::from(pat));
+            Action::PatternEdit(pat) => match CompositePattern::from(pat, &self.tree.options) {
+                Ok(pattern) => {
+                    self.pending_pattern = Some(pattern);
+                    self.selection_moved = false;
                     AppStateCmdResult::Keep
                 }
+                Err(e) => AppStateCmdResult::DisplayError(e.to_string()),
             },
             Action::Help(about) => AppStateCmdResult::NewState(Box::new(HelpState::new(&about))),
             Action::Next => {
@@ -140,7 +398,10 @@ impl AppState for BrowserState {
     }
 
     fn has_pending_tasks(&self) -> bool {
-        if self.pending_pattern.is_some() {
+        if self.pending_rebuild.is_some() {
+            return true;
+        }
+        if self.pending_pattern.is_some() || self.pending_search.is_some() {
             return true;
         }
         if self.displayed_tree().has_dir_missing_size() {
@@ -150,23 +411,43 @@ impl AppState for BrowserState {
     }
 
     fn do_pending_task(&mut self, tl: &TaskLifetime) {
-        if let Some(pat) = &mut self.pending_pattern {
-            let start = Instant::now();
+        if self.pending_rebuild.is_some() {
+            self.go_on_rebuilding(tl);
+            return;
+        }
+        if let Some(pat) = self.pending_pattern.take() {
             let mut options = self.tree.options.clone();
-            options.pattern = Some(pat.clone());
+            options.pattern = Some(pat);
             let root = self.tree.root().clone();
             let len = self.tree.lines.len() as u16;
-            let mut filtered_tree = TreeBuilder::from(root, options, len as usize).build(tl);
-            if let Some(ref mut filtered_tree) = filtered_tree {
-                info!("Tree search took {:?}", start.elapsed());
-                filtered_tree.try_select_best_match();
-                let (_, page_height) = termion::terminal_size().unwrap();
-                let mut page_height = page_height as i32;
-                page_height -= 2;
-                filtered_tree.make_selection_visible(page_height);
-            } // if none: task was cancelled from elsewhere
-            self.filtered_tree = filtered_tree;
-            self.pending_pattern = None;
+            let mut builder = TreeBuilder::from(root, options, len as usize);
+            builder.expand(&self.expanded_dirs);
+            if self.total_search {
+                builder.search_whole_tree();
+            }
+            self.pending_search = Some(builder);
+        }
+        if let Some(builder) = &mut self.pending_search {
+            let start = Instant::now();
+            match builder.gather_lines(tl, Some(SEARCH_STEP_DURATION)) {
+                None => {
+                    // the task was cancelled from elsewhere, the search
+                    //  will go on if the command doesn't change it
+                }
+                Some(false) => {
+                    let tree = builder.intermediate_tree();
+                    self.show_search_result(tree);
+                }
+                Some(true) => {
+                    let builder = self.pending_search.take().unwrap();
+                    if let Some(tolerant_builder) = builder.typo_tolerant_builder() {
+                        self.pending_search = Some(tolerant_builder);
+                    } else {
+                        self.show_search_result(builder.into_tree());
+                    }
+                }
+            }
+            info!("Tree search step took {:?}", start.elapsed());
             return;
         }
         if let Some(ref mut tree) = self.filtered_tree {
@@ -176,6 +457,19 @@ impl AppState for BrowserState {
         }
     }
 
+    fn watched_dirs(&self) -> Vec<PathBuf> {
+        self.displayed_tree()
+            .lines
+            .iter()
+            .filter(|line| line.is_dir())
+            .map(|line| line.path.clone())
+            .collect()
+    }
+
+    fn refresh(&mut self) {
+        self.start_rebuild();
+    }
+
     fn display(&mut self, screen: &mut Screen, _con: &AppContext) -> io::Result<()> {
         screen.write_tree(&self.displayed_tree())
     }
@@ -198,7 +492,23 @@ impl AppState for BrowserState {
                 )
             }
         } else if let Some(_) = &cmd.parts.pattern {
-            screen.write_status_text("Hit <enter> to select, <esc> to remove the filter")
+            let tree = self.displayed_tree();
+            if let Some(mc) = tree.match_count {
+                screen.write_status_text(&format!(
+                    "{} match{} in {} dir{}{}. Hit <enter> to select, <esc> to remove the filter",
+                    mc.nb_matches,
+                    if mc.nb_matches == 1 { "" } else { "es" },
+                    mc.nb_dirs,
+                    if mc.nb_dirs == 1 { "" } else { "s" },
+                    if mc.complete { "" } else { " (searching...)" },
+                ))
+            } else if tree.approximate {
+                screen.write_status_text(
+                    "No exact match, showing approximate ones. Hit <enter> to select, <esc> to remove the filter",
+                )
+            } else {
+                screen.write_status_text("Hit <enter> to select, <esc> to remove the filter")
+            }
         } else {
             let tree = self.displayed_tree();
             if tree.selection == 0 {
@@ -207,9 +517,14 @@ impl AppState for BrowserState {
                 )
             } else {
                 let line = &tree.lines[tree.selection];
-                screen.write_status_text(match line.is_dir() {
-                    true => "Hit <enter> to focus, or type a space then a verb",
-                    false => "Hit <enter> to open the file, or type a space then a verb",
+                screen.write_status_text(if line.is_pruning() {
+                    "Hit <enter> to show the unlisted files"
+                } else if line.is_dir() {
+                    "Hit <enter> to focus, or type a space then a verb"
+                } else if line.special_kind().is_some() {
+                    "Type a space then a verb"
+                } else {
+                    "Hit <enter> to open the file, or type a space then a verb"
                 })
             }
         }
@@ -219,10 +534,14 @@ impl AppState for BrowserState {
             Some(tree) => &tree,
             None => &self.tree,
         };
-        let total_char_size = 9;
+        let depth = match tree.options.max_depth {
+            Some(max_depth) => max_depth.to_string(),
+            None => "*".to_string(),
+        };
+        let total_char_size = 24 + depth.len() as u16;
         write!(
             screen.stdout,
-            "{}{}{}{} h:{}  gi:{}{}{}",
+            "{}{}{}{} h:{}  gi:{}  c:{}  t:{}  d:{}{}{}",
             termion::cursor::Goto(screen.w - total_char_size, screen.h),
             color::Bg(color::AnsiValue::grayscale(1)),
             termion::clear::UntilNewline,
@@ -236,6 +555,16 @@ impl AppState for BrowserState {
                 OptionBool::Yes => 'y',
                 OptionBool::No => 'n',
             },
+            match tree.options.case_sensitive {
+                OptionBool::Auto => 'a',
+                OptionBool::Yes => 'y',
+                OptionBool::No => 'n',
+            },
+            match tree.options.tolerate_typos {
+                true => 'y',
+                false => 'n',
+            },
+            depth,
             color::Bg(color::Reset),
             color::Fg(color::Reset),
         )?;
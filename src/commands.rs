@@ -1,6 +1,7 @@
-use regex::Regex;
 use termion::event::Key;
 
+use crate::filters;
+
 /// A command is the parsed representation of what the user types
 ///  in the input. It's independant of the state of the application
 ///  (verbs arent checked at this point)
@@ -14,19 +15,43 @@ pub struct Command {
 
 #[derive(Debug, Clone)]
 pub struct CommandParts {
-    pub pattern: Option<String>,
+    pub pattern: Option<String>,           // the raw pattern, as typed
+    pub pattern_expr: Option<PatternExpr>, // the parsed pattern
     pub verb: Option<String>, // may be Some("") if the user already typed the separator
 }
 
+/// The parsed form of a pattern, which may combine several simple patterns
+///  with operators: `&` (and), `|` (or), `!` (not) and parentheses.
+/// A simple pattern is a fuzzy pattern, a regex if it starts with '/', a
+///  filter like `>10M` or `type:dir`, or is prefixed with 'c/' or 'p/' for
+///  other modes (see the patterns module)
+#[derive(Debug, Clone, PartialEq)]
+pub enum PatternExpr {
+    Simple(String),
+    Not(Box<PatternExpr>),
+    And(Vec<PatternExpr>),
+    Or(Vec<PatternExpr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Simple(String),
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+}
+
 #[derive(Debug)]
 pub enum Action {
-    MoveSelection(i32),  // up (neg) or down (positive) in the list
-    ScrollPage(i32),     // in number of pages, not lines
-    OpenSelection,       // open the selected line (which can't be the root by construct)
-    VerbEdit(String),    // verb, unfinished
-    Verb(String),        // verb
-    PatternEdit(String), // a pattern being edited
-    Back,                // back to last app state, or clear pattern
+    MoveSelection(i32),       // up (neg) or down (positive) in the list
+    ScrollPage(i32),          // in number of pages, not lines
+    OpenSelection,            // open the selected line (which can't be the root by construct)
+    VerbEdit(String),         // verb, unfinished
+    Verb(String),             // verb
+    PatternEdit(PatternExpr), // a pattern being edited
+    Back,                     // back to last app state, or clear pattern
     Next,
     Help(String),
     Unparsed, // or unparsable
@@ -36,34 +61,237 @@ impl CommandParts {
     fn new() -> CommandParts {
         CommandParts {
             pattern: None,
+            pattern_expr: None,
             verb: None,
         }
     }
-    fn from(raw: &str) -> CommandParts {
+    // the input is made of an optional pattern, then an optional verb
+    //  preceded by a separator (a space or ':').
+    // As spaces are allowed in patterns (e.g. `rs | toml`), the verb is
+    //  what's left after the end of the pattern expression.
+    pub fn from(raw: &str) -> CommandParts {
         let mut cp = CommandParts::new();
-        lazy_static! {
-            static ref RE: Regex = Regex::new(
-                r"(?x)
-                ^
-                (?P<pattern>[^\s/:]+)?
-                (?:[\s:]+(?P<verb>\S*))?
-                $
-                "
-            )
-            .unwrap();
-        }
-        if let Some(c) = RE.captures(raw) {
-            if let Some(pattern) = c.name("pattern") {
-                cp.pattern = Some(String::from(pattern.as_str()));
+        let starts_with_separator = match raw.chars().next() {
+            Some(c) => c.is_whitespace() || c == ':',
+            None => {
+                return cp;
             }
-            if let Some(verb) = c.name("verb") {
-                cp.verb = Some(String::from(verb.as_str()));
+        };
+        let mut rest = raw;
+        let mut dangling_operator = false;
+        if !starts_with_separator {
+            let tokens = tokenize(raw);
+            let mut parser = PatternParser {
+                tokens: &tokens,
+                idx: 0,
+                dangling_operator: false,
+            };
+            let expr = parser.parse_or();
+            let pattern_end = match parser.idx {
+                0 => 0,
+                idx => tokens[idx - 1].2,
+            };
+            let pattern = raw[..pattern_end].trim_end();
+            if !pattern.is_empty() {
+                cp.pattern = Some(pattern.to_string());
             }
+            cp.pattern_expr = expr;
+            rest = &raw[pattern_end..];
+            dangling_operator = parser.dangling_operator;
+        }
+        if rest.is_empty() || (dangling_operator && rest.trim().is_empty()) {
+            return cp;
         }
+        let verb = rest.trim_start_matches(|c: char| c.is_whitespace() || c == ':');
+        if verb.len() == rest.len() || verb.contains(char::is_whitespace) {
+            return CommandParts::new(); // not a valid command
+        }
+        cp.verb = Some(verb.to_string());
         cp
     }
 }
 
+// cut the raw input into tokens, each one with its start and end
+//  (byte indexes). Tokenization stops at the first ':' which isn't
+//  in a regular expression, as it's a verb separator.
+fn tokenize(raw: &str) -> Vec<(Token, usize, usize)> {
+    let mut tokens = Vec::new();
+    let chars: Vec<(usize, char)> = raw.char_indices().collect();
+    let byte_idx = |i: usize| {
+        if i < chars.len() {
+            chars[i].0
+        } else {
+            raw.len()
+        }
+    };
+    let is_delimiter = |c: char| c.is_whitespace() || ":&|!()".contains(c);
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i].1;
+        let start = i;
+        let token = match c {
+            ':' => {
+                break;
+            }
+            _ if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            '&' => Token::And,
+            '|' => Token::Or,
+            '!' => Token::Not,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '/' => {
+                // a regex: everything up to the closing slash, then the flags.
+                // If there's no closing slash, up to the next space or ':'
+                let mut end = i + 1;
+                while end < chars.len() && chars[end].1 != '/' {
+                    if chars[end].1 == '\\' {
+                        end += 1;
+                    }
+                    end += 1;
+                }
+                if end < chars.len() {
+                    end += 1;
+                    while end < chars.len() && chars[end].1.is_alphanumeric() {
+                        end += 1;
+                    }
+                } else {
+                    end = i + 1;
+                    while end < chars.len() && !chars[end].1.is_whitespace() && chars[end].1 != ':'
+                    {
+                        end += 1;
+                    }
+                }
+                i = end - 1;
+                Token::Simple(raw[byte_idx(start)..byte_idx(end)].to_string())
+            }
+            'c' if raw[byte_idx(i)..].starts_with("c/\"") => {
+                // a quoted content needle, which may contain spaces, ':' or
+                //  operators: everything up to the closing quote
+                let mut end = i + 3;
+                while end < chars.len() && chars[end].1 != '"' {
+                    if chars[end].1 == '\\' {
+                        end += 1;
+                    }
+                    end += 1;
+                }
+                end = (end + 1).min(chars.len());
+                i = end - 1;
+                Token::Simple(raw[byte_idx(start)..byte_idx(end)].to_string())
+            }
+            _ => {
+                let mut end = i + 1;
+                while end < chars.len() && !is_delimiter(chars[end].1) {
+                    end += 1;
+                }
+                // in filters like `type:dir`, the ':' isn't a separator (but
+                //  it is in `type:e`, which is the `e` verb on a pattern)
+                if end < chars.len() && chars[end].1 == ':' {
+                    let mut value_end = end + 1;
+                    while value_end < chars.len() && !is_delimiter(chars[value_end].1) {
+                        value_end += 1;
+                    }
+                    if filters::is_colon_filter(&raw[byte_idx(start)..byte_idx(value_end)]) {
+                        end = value_end;
+                    }
+                }
+                i = end - 1;
+                Token::Simple(raw[byte_idx(start)..byte_idx(end)].to_string())
+            }
+        };
+        i += 1;
+        tokens.push((token, byte_idx(start), byte_idx(i)));
+    }
+    tokens
+}
+
+// a recursive descent parser for pattern expressions, with the
+//  usual precedence (not, then and, then or).
+// It's lenient with incomplete expressions, as they're parsed while
+//  being typed: an operator without operand at the end is ignored and
+//  parentheses don't need to be closed.
+struct PatternParser<'t> {
+    tokens: &'t [(Token, usize, usize)],
+    idx: usize,
+    dangling_operator: bool, // true when the last operator lacks its operand
+}
+
+impl<'t> PatternParser<'t> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.idx).map(|t| &t.0)
+    }
+    fn parse_or(&mut self) -> Option<PatternExpr> {
+        let mut operands: Vec<PatternExpr> = self.parse_and().into_iter().collect();
+        while self.peek() == Some(&Token::Or) {
+            self.idx += 1;
+            match self.parse_and() {
+                Some(operand) => operands.push(operand),
+                None => {
+                    self.dangling_operator = true;
+                    break;
+                }
+            }
+        }
+        match operands.len() {
+            0 => None,
+            1 => operands.pop(),
+            _ => Some(PatternExpr::Or(operands)),
+        }
+    }
+    fn parse_and(&mut self) -> Option<PatternExpr> {
+        let mut operands: Vec<PatternExpr> = self.parse_unary().into_iter().collect();
+        while self.peek() == Some(&Token::And) {
+            self.idx += 1;
+            match self.parse_unary() {
+                Some(operand) => operands.push(operand),
+                None => {
+                    self.dangling_operator = true;
+                    break;
+                }
+            }
+        }
+        match operands.len() {
+            0 => None,
+            1 => operands.pop(),
+            _ => Some(PatternExpr::And(operands)),
+        }
+    }
+    fn parse_unary(&mut self) -> Option<PatternExpr> {
+        self.dangling_operator = false;
+        match self.peek() {
+            Some(Token::Simple(s)) => {
+                let expr = PatternExpr::Simple(s.to_string());
+                self.idx += 1;
+                Some(expr)
+            }
+            Some(Token::Not) => {
+                self.idx += 1;
+                match self.parse_unary() {
+                    Some(operand) => Some(PatternExpr::Not(Box::new(operand))),
+                    None => {
+                        self.dangling_operator = true;
+                        None
+                    }
+                }
+            }
+            Some(Token::Open) => {
+                self.idx += 1;
+                let expr = self.parse_or();
+                if self.peek() == Some(&Token::Close) {
+                    self.idx += 1;
+                    self.dangling_operator = false;
+                } else if expr.is_none() {
+                    self.dangling_operator = true;
+                }
+                expr
+            }
+            _ => None,
+        }
+    }
+}
+
 impl Action {
     pub fn from(cp: &CommandParts, finished: bool) -> Action {
         if let Some(verb) = &cp.verb {
@@ -75,9 +303,8 @@ impl Action {
         if finished {
             return Action::OpenSelection;
         }
-        if let Some(pattern) = &cp.pattern {
-            let pattern = pattern.as_str();
-            return Action::PatternEdit(String::from(pattern));
+        if let Some(pattern_expr) = &cp.pattern_expr {
+            return Action::PatternEdit(pattern_expr.clone());
         }
         Action::Unparsed
     }
@@ -99,6 +326,7 @@ impl Command {
         if self.parts.verb.is_some() {
             if let Some(pat) = &self.parts.pattern {
                 c.raw = pat.to_owned();
+                c.parts = CommandParts::from(&c.raw);
             }
         }
         c
@@ -108,8 +336,8 @@ impl Command {
             Key::Char('\t') => {
                 self.action = Action::Next;
             }
-            Key::Char('?') => {
-                // we might be a little more subtle in the future
+            Key::Char('?') if self.raw.is_empty() || self.parts.verb.is_some() => {
+                // in a pattern, '?' is just a char (e.g. a regex quantifier)
                 self.action = Action::Help(self.raw.to_owned());
             }
             Key::Char('\n') => {
@@ -148,3 +376,141 @@ impl Command {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn typed(input: &str) -> Command {
+        let mut cmd = Command::new();
+        for c in input.chars() {
+            cmd.add_key(Key::Char(c));
+        }
+        cmd
+    }
+
+    #[test]
+    fn question_mark_opens_help_only_outside_patterns() {
+        assert!(matches!(typed("?").action, Action::Help(_)));
+        assert!(matches!(typed("abc:?").action, Action::Help(_)));
+        let cmd = typed("/colou?r/");
+        assert_eq!(cmd.raw, "/colou?r/");
+        assert!(matches!(cmd.action, Action::PatternEdit(_)));
+    }
+
+    #[test]
+    fn quoted_content_needles_are_one_pattern() {
+        let cp = CommandParts::from(r#"c/"a: b | \"c\"" & rs :e"#);
+        assert_eq!(
+            cp.pattern_expr,
+            Some(PatternExpr::And(vec![
+                PatternExpr::Simple(r#"c/"a: b | \"c\"""#.to_string()),
+                PatternExpr::Simple("rs".to_string()),
+            ]))
+        );
+        assert_eq!(cp.verb.as_deref(), Some("e"));
+        // while it's typed, the closing quote is missing
+        let cp = CommandParts::from(r#"c/"a b"#);
+        assert_eq!(cp.pattern.as_deref(), Some(r#"c/"a b"#));
+        assert_eq!(cp.verb, None);
+    }
+
+    fn simple(s: &str) -> PatternExpr {
+        PatternExpr::Simple(s.to_string())
+    }
+
+    #[test]
+    fn tokens_have_byte_positions() {
+        let tokens = tokenize("é & !(b|/c d/i):x");
+        assert_eq!(
+            tokens,
+            vec![
+                (Token::Simple("é".to_string()), 0, 2),
+                (Token::And, 3, 4),
+                (Token::Not, 5, 6),
+                (Token::Open, 6, 7),
+                (Token::Simple("b".to_string()), 7, 8),
+                (Token::Or, 8, 9),
+                (Token::Simple("/c d/i".to_string()), 9, 15),
+                (Token::Close, 15, 16),
+            ]
+        );
+        // an unclosed regex ends at the first space or ':'
+        assert_eq!(tokenize("/a b")[0], (Token::Simple("/a".to_string()), 0, 2));
+    }
+
+    #[test]
+    fn not_binds_tighter_than_and_which_binds_tighter_than_or() {
+        let cp = CommandParts::from("a | b & !c");
+        assert_eq!(
+            cp.pattern_expr,
+            Some(PatternExpr::Or(vec![
+                simple("a"),
+                PatternExpr::And(vec![simple("b"), PatternExpr::Not(Box::new(simple("c")))]),
+            ]))
+        );
+    }
+
+    #[test]
+    fn parentheses_group_operands() {
+        let expected = Some(PatternExpr::And(vec![
+            PatternExpr::Or(vec![simple("a"), simple("b")]),
+            simple("c"),
+        ]));
+        assert_eq!(CommandParts::from("(a | b) & c").pattern_expr, expected);
+        assert_eq!(CommandParts::from("(a|b)&c").pattern_expr, expected);
+        assert_eq!(
+            CommandParts::from("!!(a").pattern_expr,
+            Some(PatternExpr::Not(Box::new(PatternExpr::Not(Box::new(
+                simple("a")
+            )))))
+        );
+    }
+
+    #[test]
+    fn incomplete_expressions_are_parsed_while_typed() {
+        for raw in &["a &", "a | ", "a & !", "(a", "a & ("] {
+            let cp = CommandParts::from(raw);
+            assert_eq!(cp.pattern_expr, Some(simple("a")), "parsing {:?}", raw);
+            assert_eq!(cp.verb, None, "parsing {:?}", raw);
+        }
+    }
+
+    #[test]
+    fn the_verb_follows_the_pattern() {
+        let cp = CommandParts::from("a & b:rm");
+        assert_eq!(cp.pattern.as_deref(), Some("a & b"));
+        assert_eq!(cp.verb.as_deref(), Some("rm"));
+        let cp = CommandParts::from("a b");
+        assert_eq!(cp.pattern.as_deref(), Some("a"));
+        assert_eq!(cp.verb.as_deref(), Some("b"));
+        let cp = CommandParts::from(":e");
+        assert_eq!(cp.pattern, None);
+        assert_eq!(cp.verb.as_deref(), Some("e"));
+        // ':' and spaces in a regex don't start a verb
+        let cp = CommandParts::from("/a: b/ e");
+        assert_eq!(cp.pattern_expr, Some(simple("/a: b/")));
+        assert_eq!(cp.verb.as_deref(), Some("e"));
+        // what's after the verb makes the command invalid
+        let cp = CommandParts::from("a b c");
+        assert_eq!(cp.pattern, None);
+        assert_eq!(cp.verb, None);
+    }
+
+    #[test]
+    fn filters_with_a_colon_are_recognized_when_valid() {
+        let cp = CommandParts::from("type:dir & ext:rs:e");
+        assert_eq!(
+            cp.pattern_expr,
+            Some(PatternExpr::And(vec![simple("type:dir"), simple("ext:rs")]))
+        );
+        assert_eq!(cp.verb.as_deref(), Some("e"));
+        // not (yet) a valid filter: a pattern, then a verb
+        let cp = CommandParts::from("type:e");
+        assert_eq!(cp.pattern_expr, Some(simple("type")));
+        assert_eq!(cp.verb.as_deref(), Some("e"));
+        let cp = CommandParts::from("type:");
+        assert_eq!(cp.pattern_expr, Some(simple("type")));
+        assert_eq!(cp.verb.as_deref(), Some(""));
+    }
+}
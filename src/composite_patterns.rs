@@ -0,0 +1,230 @@
+//! a composite pattern combines simple patterns with boolean
+//!  operators (e.g. `foo & !test` or `(rs | toml)`).
+//! It's the pattern which is applied to the tree.
+
+use crate::commands::PatternExpr;
+use crate::errors::PatternError;
+use crate::patterns::{Candidate, Match, Pattern};
+use crate::tree_options::TreeOptions;
+
+// score of a match by a negation, which says nothing about relevance
+const SCORE_NOT: i32 = 0;
+
+#[derive(Debug, Clone)]
+pub enum CompositePattern {
+    Simple {
+        raw: String, // as typed, so that the pattern can be rebuilt
+        pattern: Pattern,
+    },
+    Not(Box<CompositePattern>),
+    And(Vec<CompositePattern>),
+    Or(Vec<CompositePattern>),
+}
+
+impl CompositePattern {
+    pub fn from(
+        expr: &PatternExpr,
+        options: &TreeOptions,
+    ) -> Result<CompositePattern, PatternError> {
+        let from_all = |operands: &[PatternExpr]| {
+            operands
+                .iter()
+                .map(|operand| CompositePattern::from(operand, options))
+                .collect::<Result<Vec<_>, _>>()
+        };
+        Ok(match expr {
+            PatternExpr::Simple(raw) => CompositePattern::Simple {
+                raw: raw.to_string(),
+                pattern: Pattern::from(raw, options)?,
+            },
+            PatternExpr::Not(operand) => {
+                CompositePattern::Not(Box::new(CompositePattern::from(operand, options)?))
+            }
+            PatternExpr::And(operands) => CompositePattern::And(from_all(operands)?),
+            PatternExpr::Or(operands) => CompositePattern::Or(from_all(operands)?),
+        })
+    }
+    // the expression from which the pattern was built, to
+    //  rebuild it with other options
+    pub fn to_expr(&self) -> PatternExpr {
+        match self {
+            CompositePattern::Simple { raw, .. } => PatternExpr::Simple(raw.to_string()),
+            CompositePattern::Not(operand) => PatternExpr::Not(Box::new(operand.to_expr())),
+            CompositePattern::And(operands) => {
+                PatternExpr::And(operands.iter().map(CompositePattern::to_expr).collect())
+            }
+            CompositePattern::Or(operands) => {
+                PatternExpr::Or(operands.iter().map(CompositePattern::to_expr).collect())
+            }
+        }
+    }
+    // return a match if the candidate satisfies the expression.
+    // The score of a conjunction is the sum of the scores of its
+    //  operands, while a disjunction takes the best matching operand.
+    pub fn test(&self, candidate: &Candidate<'_>) -> Option<Match> {
+        match self {
+            CompositePattern::Simple { pattern, .. } => pattern.test(candidate),
+            CompositePattern::Not(operand) => match operand.test(candidate) {
+                Some(_) => None,
+                None => Some(Match {
+                    score: SCORE_NOT,
+                    pos: Vec::new(),
+                }),
+            },
+            CompositePattern::And(operands) => {
+                let mut score = 0;
+                let mut pos = Vec::new();
+                for operand in operands {
+                    let m = operand.test(candidate)?;
+                    score += m.score;
+                    pos.extend(m.pos);
+                }
+                pos.sort_unstable();
+                pos.dedup();
+                Some(Match { score, pos })
+            }
+            CompositePattern::Or(operands) => operands
+                .iter()
+                .filter_map(|operand| operand.test(candidate))
+                .max_by_key(|m| m.score),
+        }
+    }
+    // return a copy of the expression where the patterns tolerate
+    //  typos, or None if no pattern can be made tolerant.
+    // Negated patterns aren't changed as a tolerant negation would
+    //  exclude more files.
+    pub fn with_typos(&self) -> Option<CompositePattern> {
+        match self {
+            CompositePattern::Simple { raw, pattern } => {
+                pattern
+                    .with_typos()
+                    .map(|pattern| CompositePattern::Simple {
+                        raw: raw.to_string(),
+                        pattern,
+                    })
+            }
+            CompositePattern::Not(_) => None,
+            CompositePattern::And(operands) => {
+                CompositePattern::operands_with_typos(operands).map(CompositePattern::And)
+            }
+            CompositePattern::Or(operands) => {
+                CompositePattern::operands_with_typos(operands).map(CompositePattern::Or)
+            }
+        }
+    }
+    fn operands_with_typos(operands: &[CompositePattern]) -> Option<Vec<CompositePattern>> {
+        let mut changed = false;
+        let operands = operands
+            .iter()
+            .map(|operand| match operand.with_typos() {
+                Some(tolerant) => {
+                    changed = true;
+                    tolerant
+                }
+                None => operand.clone(),
+            })
+            .collect();
+        if changed {
+            Some(operands)
+        } else {
+            None
+        }
+    }
+    // tell whether some part of the expression needs the metadata
+    //  of files, which must then be given in candidates
+    pub fn needs_metadata(&self) -> bool {
+        match self {
+            CompositePattern::Simple { pattern, .. } => pattern.needs_metadata(),
+            CompositePattern::Not(operand) => operand.needs_metadata(),
+            CompositePattern::And(operands) | CompositePattern::Or(operands) => {
+                operands.iter().any(CompositePattern::needs_metadata)
+            }
+        }
+    }
+    // tell whether some part of the expression needs the content of
+    //  files, which means files can only be tested with their path
+    pub fn has_content_search(&self) -> bool {
+        match self {
+            CompositePattern::Simple { pattern, .. } => pattern.is_content_search(),
+            CompositePattern::Not(operand) => operand.has_content_search(),
+            CompositePattern::And(operands) | CompositePattern::Or(operands) => {
+                operands.iter().any(CompositePattern::has_content_search)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::CommandParts;
+
+    fn pattern(raw: &str) -> Result<CompositePattern, PatternError> {
+        let expr = CommandParts::from(raw).pattern_expr.unwrap();
+        CompositePattern::from(&expr, &TreeOptions::new())
+    }
+
+    fn test(pattern: &CompositePattern, name: &str) -> Option<Match> {
+        pattern.test(&Candidate {
+            name,
+            subpath: name,
+            path: None,
+            task_lifetime: None,
+            metadata: None,
+        })
+    }
+
+    #[test]
+    fn conjunctions_need_all_operands() {
+        let p = pattern("main & rs").unwrap();
+        let m = test(&p, "main.rs").unwrap();
+        assert_eq!(m.pos, vec![0, 1, 2, 3, 5, 6]);
+        let main = test(&pattern("main").unwrap(), "main.rs").unwrap();
+        let rs = test(&pattern("rs").unwrap(), "main.rs").unwrap();
+        assert_eq!(m.score, main.score + rs.score);
+        assert!(test(&p, "main.go").is_none());
+    }
+
+    #[test]
+    fn disjunctions_take_the_best_operand() {
+        let p = pattern("/^m/ | main").unwrap();
+        let regex = test(&pattern("/^m/").unwrap(), "main.rs").unwrap();
+        let fuzzy = test(&pattern("main").unwrap(), "main.rs").unwrap();
+        assert_eq!(
+            test(&p, "main.rs").unwrap().score,
+            regex.score.max(fuzzy.score)
+        );
+        assert!(test(&p, "lib.rs").is_none());
+    }
+
+    #[test]
+    fn negations_match_what_their_operand_does_not() {
+        let p = pattern("rs & !test").unwrap();
+        assert!(test(&p, "main.rs").is_some());
+        assert!(test(&p, "test.rs").is_none());
+        let m = test(&pattern("!test").unwrap(), "main.rs").unwrap();
+        assert_eq!(m.score, SCORE_NOT);
+        assert!(m.pos.is_empty());
+    }
+
+    #[test]
+    fn an_invalid_operand_makes_the_pattern_invalid() {
+        assert!(matches!(
+            pattern("a | (b & /(/)"),
+            Err(PatternError::InvalidRegex { .. })
+        ));
+        assert!(matches!(
+            pattern("a & !/x/z"),
+            Err(PatternError::UnknownRegexFlag { bad: 'z' })
+        ));
+    }
+
+    #[test]
+    fn negations_do_not_tolerate_typos() {
+        assert!(pattern("!abcdef").unwrap().with_typos().is_none());
+        let tolerant = pattern("abcdef & !ghijkl").unwrap().with_typos().unwrap();
+        assert!(test(&tolerant, "abcdxf").is_some());
+        assert!(test(&tolerant, "abcdef-ghijxl").is_some());
+        assert!(test(&tolerant, "abcdef-ghijkl").is_none());
+    }
+}
@@ -9,10 +9,14 @@ use toml::{self, Value};
 use custom_error::custom_error;
 use directories::ProjectDirs;
 
+use crate::fuzzy_patterns::FuzzyWeights;
+use crate::tree_options::SortOrder;
+
 custom_error! {pub ConfError
     Io{source: io::Error}           = "unable to read from the file",
     Toml{source: toml::de::Error}   = "unable to parse TOML",
     MissingField{txt: String}       = "missing field in conf",
+    InvalidField{txt: String}       = "invalid value for field in conf",
 }
 
 // what's needed to handle a verb
@@ -26,6 +30,8 @@ pub struct VerbConf {
 #[derive(Debug)]
 pub struct Conf {
     pub verbs: Vec<VerbConf>,
+    pub fuzzy_weights: FuzzyWeights,
+    pub sort: SortOrder,
 }
 
 fn string_field(value: &Value, field_name: &str) -> Result<String, ConfError> {
@@ -37,6 +43,38 @@ fn string_field(value: &Value, field_name: &str) -> Result<String, ConfError> {
     }
 }
 
+// read an optional integer field, replacing the default value
+fn read_int_field(value: &Value, field_name: &str, dst: &mut i32) -> Result<(), ConfError> {
+    match value.get(field_name) {
+        Some(Value::Integer(i)) => {
+            *dst = *i as i32;
+            Ok(())
+        }
+        Some(_) => Err(ConfError::InvalidField {
+            txt: field_name.to_owned(),
+        }),
+        None => Ok(()),
+    }
+}
+
+fn read_fuzzy_weights(value: &Value) -> Result<FuzzyWeights, ConfError> {
+    let mut weights = FuzzyWeights::default();
+    read_int_field(value, "match", &mut weights.bonus_match)?;
+    read_int_field(value, "exact", &mut weights.bonus_exact)?;
+    read_int_field(value, "start", &mut weights.bonus_start)?;
+    read_int_field(value, "word_start", &mut weights.bonus_word_start)?;
+    read_int_field(value, "camel_hump", &mut weights.bonus_camel_hump)?;
+    read_int_field(
+        value,
+        "candidate_length",
+        &mut weights.bonus_candidate_length,
+    )?;
+    read_int_field(value, "match_length", &mut weights.bonus_length)?;
+    read_int_field(value, "last_component", &mut weights.bonus_last_component)?;
+    read_int_field(value, "typo", &mut weights.bonus_typo)?;
+    Ok(weights)
+}
+
 impl Conf {
     pub fn default_location() -> PathBuf {
         let dirs = match ProjectDirs::from("org", "dystroy", "broot") {
@@ -86,11 +124,35 @@ impl Conf {
                 });
             }
         }
-        Ok(Conf { verbs })
+        let fuzzy_weights = match root.get("fuzzy_scoring") {
+            Some(value) => read_fuzzy_weights(value)?,
+            None => FuzzyWeights::default(),
+        };
+        let sort = match root.get("sort") {
+            Some(Value::String(s)) => s.parse().map_err(|_| ConfError::InvalidField {
+                txt: "sort".to_owned(),
+            })?,
+            Some(_) => {
+                return Err(ConfError::InvalidField {
+                    txt: "sort".to_owned(),
+                });
+            }
+            None => SortOrder::Name,
+        };
+        Ok(Conf {
+            verbs,
+            fuzzy_weights,
+            sort,
+        })
     }
 }
 
 const DEFAULT_CONF_FILE: &str = r#"
+# the order of the children of directories: "name" (the default),
+# "size" (biggest first), "date" (most recently modified first),
+# "extension" or "type" (directories first)
+# sort = "name"
+
 # verbs define the commands you can call on files
 # 'invocation' can be a letter or a word
 # 'execution' is either a command, where {file} will be replaced by the selected line,
@@ -98,15 +160,29 @@ const DEFAULT_CONF_FILE: &str = r#"
 #   ":back"              : reverts to the previous state, or quit the application if it's the first one (mapped to <esc>)
 #   ":cd"                : changes directory (see https://github.com/Canop/broot)
 #   ":print_path"        : outputs the path to stdout or to a file provided with --out
+#   ":expand"            : lists all the children of the selected directory, in place (mapped to <enter> on "unlisted" lines)
+#   ":jump"              : goes to the directory of the target of the selected link, with the target selected
 #   ":focus"             : displays the tree of that directory, keeps the current search pattern
 #   ":open"              : tries to open the file according to OS settings (e.g. using xdg-open) (mapped to <enter> on files)
 #   ":parent"            : moves to the parent directory
 #   ":quit"              : quits the application
+#   ":refresh"           : reads the displayed directories again, keeping the selection
+#   ":total_search"      : searches the whole tree with the current pattern, counting the matches
 #   ":toggle_hidden"     : toggles showing hidden files
 #   ":toggle_git_ignore" : toggles hiding files matching .gitignore exclusions
 #   ":toggle_sizes"      : toggles displaying file and folder sizes
 #   ":toggle_perm"       : toggles displaying file and folder sizes
 #   ":toggle_files"      : toggles showing files or just directories
+#   ":toggle_links"      : toggles exploring the directories symbolic links point to
+#   ":toggle_case"       : switches between smart case, case sensitive and case insensitive searches
+#   ":toggle_typos"      : toggles searching again with tolerance to typos when there are few matches
+#   ":depth+"            : displays one more level of the tree
+#   ":depth-"            : displays one less level of the tree
+#   ":sort_by_name"      : sorts the children of directories by name (the default)
+#   ":sort_by_size"      : sorts by size, biggest first, and displays sizes
+#   ":sort_by_date"      : sorts by modification date, most recent first
+#   ":sort_by_extension" : sorts by extension, then by name
+#   ":sort_by_type"      : sorts directories first, then files, by name
 
 [[verbs]]
 name = "cd"
@@ -123,6 +199,11 @@ name = "edit"
 invocation = "e"
 execution = "/usr/bin/nvim {file}"
 
+[[verbs]]
+name = "total search"
+invocation = "total"
+execution = ":total_search"
+
 [[verbs]]
 name = "toggle hidden"
 invocation = "h"
@@ -138,6 +219,16 @@ name = "toggle gitignore"
 invocation = "gi"
 execution = ":toggle_git_ignore"
 
+[[verbs]]
+name = "toggle case"
+invocation = "cs"
+execution = ":toggle_case"
+
+[[verbs]]
+name = "toggle typos"
+invocation = "ty"
+execution = ":toggle_typos"
+
 [[verbs]]
 name = "open"
 invocation = "o"
@@ -148,18 +239,97 @@ name = "toggle sizes"
 invocation = "s"
 execution = ":toggle_sizes"
 
+[[verbs]]
+name = "toggle links"
+invocation = "links"
+execution = ":toggle_links"
+
 [[verbs]]
 name = "toggle permissions"
 invocation = "perm"
 execution = ":toggle_perm"
 
+[[verbs]]
+name = "more depth"
+invocation = "d+"
+execution = ":depth+"
+
+[[verbs]]
+name = "less depth"
+invocation = "d-"
+execution = ":depth-"
+
+[[verbs]]
+name = "sort by name"
+invocation = "sn"
+execution = ":sort_by_name"
+
+[[verbs]]
+name = "sort by size"
+invocation = "ss"
+execution = ":sort_by_size"
+
+[[verbs]]
+name = "sort by date"
+invocation = "sd"
+execution = ":sort_by_date"
+
+[[verbs]]
+name = "sort by extension"
+invocation = "se"
+execution = ":sort_by_extension"
+
+[[verbs]]
+name = "sort by type"
+invocation = "st"
+execution = ":sort_by_type"
+
+[[verbs]]
+name = "expand"
+invocation = "x"
+execution = ":expand"
+
+[[verbs]]
+name = "jump"
+invocation = "j"
+execution = ":jump"
+
 [[verbs]]
 name = "parent"
 invocation = "p"
 execution = ":parent"
 
+[[verbs]]
+name = "refresh"
+invocation = "r"
+execution = ":refresh"
+
 [[verbs]]
 name = "quit"
 invocation = "q"
 execution = ":quit"
+
+# The weights used to sort fuzzy matches can be changed in the
+# [fuzzy_scoring] section. The values are integers, the ones below
+# are the defaults.
+#   match            : base score of any match
+#   exact            : bonus when the pattern is the whole name
+#   start            : bonus when the first char of the name matches
+#   word_start       : bonus per matching char at the start of a word (after '_', '-', '.', ' ', etc.)
+#   camel_hump       : bonus per matching uppercase char following a lowercase one
+#   candidate_length : bonus per char of the name (negative, to favor short names)
+#   match_length     : bonus per char spanned by the match (negative, to favor compact matches)
+#   last_component   : bonus per char matched in the file name in path searches ('p/')
+#   typo             : bonus per typo when tolerating typos (negative)
+#
+# [fuzzy_scoring]
+# match = 10000
+# exact = 1000
+# start = 30
+# word_start = 50
+# camel_hump = 40
+# candidate_length = -1
+# match_length = -10
+# last_component = 50
+# typo = -1000
 "#;
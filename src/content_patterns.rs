@@ -0,0 +1,151 @@
+//! a pattern looking for a string in the content of files
+//!  (e.g. `c/TODO`). Contrary to the other patterns it can't
+//!  be tested on a name, the file must be read.
+
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+
+use regex::bytes::{Regex, RegexBuilder};
+
+use crate::patterns::Match;
+use crate::task_sync::TaskLifetime;
+
+// weights used in match score computing
+const BONUS_MATCH: i32 = 10_000;
+const BONUS_OCCURRENCE: i32 = 10; // per occurrence of the needle, up to MAX_COUNTED_OCCURRENCES
+const MAX_COUNTED_OCCURRENCES: usize = 100;
+
+// files bigger than that aren't searched
+const MAX_FILE_SIZE: u64 = 10 * 1024 * 1024;
+
+// files are read by chunks of that size, so that the search can be
+//  interrupted in the middle of a big file
+const CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Clone)]
+pub struct ContentPattern {
+    rex: Regex,           // the escaped needle, so that the case of all letters can be ignored
+    max_match_len: usize, // in bytes, 0 when the needle is empty
+}
+
+impl ContentPattern {
+    pub fn from(pat: &str, case_sensitive: bool) -> ContentPattern {
+        let rex = RegexBuilder::new(&regex::escape(pat))
+            .case_insensitive(!case_sensitive)
+            .build()
+            .expect("an escaped needle is a valid regex");
+        // when ignoring case, a letter may not have the same UTF-8 length
+        //  as the one it matches (e.g. 'k' and the Kelvin sign)
+        let max_match_len = match case_sensitive {
+            true => pat.len(),
+            false => 4 * pat.chars().count(),
+        };
+        ContentPattern { rex, max_match_len }
+    }
+    // return a match if the file at the given path contains the needle.
+    // Binary files (the ones containing a NUL byte) never match.
+    // The match has no position as nothing in the name is matching.
+    // When the task expires, the reading stops and there's no match (the
+    //  caller must check the task lifetime to know the result is unreliable)
+    pub fn test_file(&self, path: &Path, tl: Option<&TaskLifetime>) -> Option<Match> {
+        match fs::metadata(path) {
+            Ok(md) if md.len() <= MAX_FILE_SIZE => {}
+            _ => {
+                return None;
+            }
+        }
+        let mut file = File::open(path).ok()?;
+        // the end of the previous chunk is kept, as the needle may
+        //  be cut between two chunks
+        let kept_len = self.max_match_len.saturating_sub(1);
+        let mut buffer = vec![0; kept_len + CHUNK_SIZE];
+        let mut nb_kept = 0;
+        let mut nb_occurrences = 0;
+        loop {
+            if tl.is_some_and(TaskLifetime::is_expired) {
+                return None;
+            }
+            let nb_read = match file.read(&mut buffer[nb_kept..]) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(_) => {
+                    return None;
+                }
+            };
+            let chunk = &buffer[nb_kept..nb_kept + nb_read];
+            if chunk.contains(&0) {
+                return None; // binary file
+            }
+            let end = nb_kept + nb_read;
+            if nb_occurrences < MAX_COUNTED_OCCURRENCES {
+                nb_occurrences += self.count_occurrences(&buffer[..end], nb_kept);
+            }
+            let new_kept = kept_len.min(end);
+            buffer.copy_within(end - new_kept..end, 0);
+            nb_kept = new_kept;
+        }
+        if self.max_match_len == 0 {
+            nb_occurrences = 1; // an empty needle is found in every text file
+        }
+        let nb_occurrences = nb_occurrences.min(MAX_COUNTED_OCCURRENCES);
+        if nb_occurrences == 0 {
+            return None;
+        }
+        Some(Match {
+            score: BONUS_MATCH + BONUS_OCCURRENCE * nb_occurrences as i32,
+            pos: Vec::new(),
+        })
+    }
+    // count the occurrences ending in the new part of the content, the
+    //  ones ending in the kept part having been counted with the previous chunk
+    fn count_occurrences(&self, content: &[u8], nb_kept: usize) -> usize {
+        if self.max_match_len == 0 {
+            return 0;
+        }
+        self.rex
+            .find_iter(content)
+            .filter(|m| m.end() > nb_kept)
+            .take(MAX_COUNTED_OCCURRENCES)
+            .count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dirs::TestDir;
+
+    #[test]
+    fn needles_cut_between_chunks_are_counted() {
+        let dir = TestDir::new("content-pattern");
+        let mut content = vec![b'x'; CHUNK_SIZE - 2];
+        content.extend_from_slice(b"TODO and todo");
+        let path = dir.file("cut.txt", &content);
+        let sensitive = ContentPattern::from("TODO", true).test_file(&path, None);
+        let insensitive = ContentPattern::from("todo", false).test_file(&path, None);
+        assert_eq!(sensitive.unwrap().score, BONUS_MATCH + BONUS_OCCURRENCE);
+        assert_eq!(
+            insensitive.unwrap().score,
+            BONUS_MATCH + 2 * BONUS_OCCURRENCE
+        );
+    }
+
+    #[test]
+    fn case_is_ignored_for_all_letters() {
+        let dir = TestDir::new("content-pattern-case");
+        let mut content = vec![b'x'; CHUNK_SIZE - 3];
+        content.extend_from_slice("ÉTÉ et été".as_bytes());
+        let path = dir.file("cut.txt", &content);
+        let sensitive = ContentPattern::from("été", true).test_file(&path, None);
+        let insensitive = ContentPattern::from("été", false).test_file(&path, None);
+        assert_eq!(sensitive.unwrap().score, BONUS_MATCH + BONUS_OCCURRENCE);
+        assert_eq!(
+            insensitive.unwrap().score,
+            BONUS_MATCH + 2 * BONUS_OCCURRENCE
+        );
+        assert!(ContentPattern::from("ete", false)
+            .test_file(&path, None)
+            .is_none());
+    }
+}
@@ -8,3 +8,10 @@ custom_error! {pub ProgramError
     Conf {source: conf::ConfError}   = "Bad configuration",
     ArgParse {bad: String, valid: String} = "{:?} can't be parsed (valid values: {:?}",
 }
+
+custom_error! {pub PatternError
+    InvalidRegex {source: regex::Error} = "Invalid regular expression",
+    UnknownRegexFlag {bad: char}        = "Unknown regular expression flag: {bad:?}",
+    InvalidGlob {source: glob::PatternError} = "Invalid glob pattern",
+    InvalidFilter {txt: String}         = "Invalid filter: {txt:?}",
+}
@@ -0,0 +1,92 @@
+//! a pattern looking for a literal substring in names
+//!  (e.g. `e/mod.rs`), for when the fuzzy search finds too
+//!  many files. Unless the search is case sensitive, the case
+//!  of all letters is ignored, but diacritics never are.
+
+use crate::normalization::NormalizedChars;
+use crate::patterns::Match;
+
+// weights used in match score computing
+const BONUS_MATCH: i32 = 10_000;
+const BONUS_START: i32 = 10; // when the substring starts the name
+const BONUS_CANDIDATE_LENGTH: i32 = -1; // per char
+
+#[derive(Debug, Clone)]
+pub struct ExactPattern {
+    chars: Box<[char]>, // normalized characters
+    case_sensitive: bool,
+}
+
+impl ExactPattern {
+    pub fn from(pat: &str, case_sensitive: bool) -> ExactPattern {
+        let normalized = NormalizedChars::from(pat, !case_sensitive, true);
+        ExactPattern {
+            chars: normalized.chars.into_boxed_slice(),
+            case_sensitive,
+        }
+    }
+    // return a match if the candidate contains the substring. The
+    //  positions are the ones of its first occurrence.
+    pub fn test(&self, candidate: &str) -> Option<Match> {
+        if self.chars.is_empty() {
+            return None;
+        }
+        let normalized = NormalizedChars::from(candidate, !self.case_sensitive, true);
+        let start = normalized
+            .chars
+            .windows(self.chars.len())
+            .position(|window| window == &*self.chars)?;
+        let pos: Vec<usize> = (start..start + self.chars.len()).collect();
+        let mut score = BONUS_MATCH + BONUS_CANDIDATE_LENGTH * (normalized.chars.len() as i32);
+        if start == 0 {
+            score += BONUS_START;
+        }
+        Some(Match {
+            score,
+            pos: normalized.orig_positions(&pos),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pos(pat: &str, case_sensitive: bool, candidate: &str) -> Option<Vec<usize>> {
+        ExactPattern::from(pat, case_sensitive)
+            .test(candidate)
+            .map(|m| m.pos)
+    }
+
+    #[test]
+    fn the_first_occurrence_is_highlighted() {
+        assert_eq!(pos("mod", true, "a_mod_mod.rs"), Some(vec![2, 3, 4]));
+        assert_eq!(pos("mdo", true, "a_mod_mod.rs"), None);
+        assert_eq!(pos("", true, "a_mod_mod.rs"), None);
+    }
+
+    #[test]
+    fn case_may_be_ignored_but_not_diacritics() {
+        assert_eq!(pos("mod", true, "MOD.rs"), None);
+        assert_eq!(pos("mod", false, "MOD.rs"), Some(vec![0, 1, 2]));
+        assert_eq!(pos("été", false, "ÉTÉ.txt"), Some(vec![0, 1, 2]));
+        assert_eq!(pos("ete", false, "été.txt"), None);
+    }
+
+    #[test]
+    fn decomposed_chars_are_highlighted_whole() {
+        assert_eq!(pos("é", true, "e\u{301}t"), Some(vec![0, 1]));
+        assert_eq!(pos("e\u{301}t", true, "ét"), Some(vec![0, 1]));
+    }
+
+    #[test]
+    fn names_starting_with_the_substring_are_preferred() {
+        let score = |candidate| {
+            ExactPattern::from("mod", true)
+                .test(candidate)
+                .unwrap()
+                .score
+        };
+        assert!(score("mod.rs") > score("amod.r"));
+    }
+}
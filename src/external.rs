@@ -1,26 +1,34 @@
-use std::io;
+use std::ffi::OsString;
+use std::io::{self, Write};
+use std::os::unix::ffi::OsStrExt;
 use std::path::PathBuf;
 use std::process::Command;
 
 // description of a possible launch of an external program
 // (might be more complex, and a sequence of things to try, in the future)
+// The parts are OS strings, so that paths are passed exactly, even when
+//  they're not valid UTF-8
 #[derive(Debug)]
 pub struct Launchable {
-    exe: String,
-    args: Vec<String>,
+    exe: OsString,
+    args: Vec<OsString>,
     pub just_print: bool, // this part of the API will change
 }
 
 impl Launchable {
     pub fn opener(path: &PathBuf) -> io::Result<Launchable> {
-        Launchable::from(&format!("xdg-open {}", &path.to_string_lossy()))
+        Launchable::from(vec![
+            OsString::from("xdg-open"),
+            path.clone().into_os_string(),
+        ])
     }
-    pub fn from(launch_string: &str) -> io::Result<Launchable> {
-        let mut tokens = launch_string.split_whitespace().map(|t| t.to_string());
-        match tokens.next() {
+    // the first part is the executable, the other ones its arguments
+    pub fn from(parts: Vec<OsString>) -> io::Result<Launchable> {
+        let mut parts = parts.into_iter();
+        match parts.next() {
             Some(exe) => Ok(Launchable {
                 exe,
-                args: tokens.collect(),
+                args: parts.collect(),
                 just_print: false,
             }),
             None => Err(io::Error::new(io::ErrorKind::Other, "Empty launch string")),
@@ -28,11 +36,13 @@ impl Launchable {
     }
     pub fn execute(&self) -> io::Result<()> {
         if self.just_print {
-            print!("{}", &self.exe);
+            let mut stdout = io::stdout();
+            stdout.write_all(self.exe.as_bytes())?;
             for arg in &self.args {
-                print!(" {}", &arg);
+                stdout.write_all(b" ")?;
+                stdout.write_all(arg.as_bytes())?;
             }
-            println!();
+            writeln!(stdout)?;
         } else {
             Command::new(&self.exe)
                 .args(self.args.iter())
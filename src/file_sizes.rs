@@ -3,6 +3,8 @@
 //  same directories again and again.
 // Hard links are checked to avoid counting
 //  twice an inode.
+// When asked, the computation doesn't leave the file
+//  system of the displayed root (like `du -x`).
 use crate::task_sync::TaskLifetime;
 use std::collections::{HashMap, HashSet};
 use std::fs;
@@ -14,9 +16,16 @@ use std::time::Instant;
 
 const SIZE_NAMES: &[&str] = &["", "KB", "MB", "GB", "TB", "PB", "EB", "ZB", "YB"]; // YB: for when your disk is bigger than 1024 ZB
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Size(u64);
 
+lazy_static! {
+    // the computed sizes of directories, the key also holding the
+    //  device of the root when the computation stayed on its file system
+    static ref size_cache_mutex: Mutex<HashMap<(PathBuf, Option<u64>), Size>> =
+        Mutex::new(HashMap::new());
+}
+
 impl Size {
     pub fn from_file(path: &Path) -> Size {
         Size(match fs::metadata(path) {
@@ -25,18 +34,24 @@ impl Size {
         })
     }
 
-    pub fn from_dir(path: &Path, tl: &TaskLifetime) -> Option<Size> {
-        lazy_static! {
-            static ref size_cache_mutex: Mutex<HashMap<PathBuf, Size>> = Mutex::new(HashMap::new());
-        }
+    // compute the size of a directory. When root_dev is given, only the
+    //  directories on this device are measured (a mount point has a size of 0)
+    pub fn from_dir(path: &Path, root_dev: Option<u64>, tl: &TaskLifetime) -> Option<Size> {
         let mut size_cache = size_cache_mutex.lock().unwrap();
-        if let Some(s) = size_cache.get(path) {
+        let key = (PathBuf::from(path), root_dev);
+        if let Some(s) = size_cache.get(&key) {
             return Some(*s);
         }
         let start = Instant::now();
         let mut s = Size::from(0);
         let mut dirs: Vec<PathBuf> = Vec::new();
-        dirs.push(PathBuf::from(path));
+        let on_root_dev = match root_dev {
+            Some(dev) => fs::metadata(path).is_ok_and(|md| md.dev() == dev),
+            None => true,
+        };
+        if on_root_dev {
+            dirs.push(PathBuf::from(path));
+        }
         let mut inodes: HashSet<u64> = HashSet::new(); // to avoid counting twice an inode
         let mut nb_duplicate_inodes = 0;
         while let Some(open_dir) = dirs.pop() {
@@ -45,7 +60,9 @@ impl Size {
                     if let Ok(e) = e {
                         if let Ok(md) = e.metadata() {
                             if md.is_dir() {
-                                dirs.push(e.path());
+                                if root_dev.is_none_or(|dev| dev == md.dev()) {
+                                    dirs.push(e.path());
+                                }
                             } else if md.nlink() > 1 && !inodes.insert(md.ino()) {
                                 // it was already in the set
                                 nb_duplicate_inodes += 1;
@@ -60,7 +77,7 @@ impl Size {
                 return None;
             }
         }
-        size_cache.insert(PathBuf::from(path), s);
+        size_cache.insert(key, s);
         debug!("size computation for {:?} took {:?}", path, start.elapsed());
         if nb_duplicate_inodes > 0 {
             debug!(
@@ -71,6 +88,13 @@ impl Size {
         Some(s)
     }
 
+    // forget the computed sizes of the directory and of the directories
+    //  under it, so that they're computed again
+    pub fn clear_cache(path: &Path) {
+        let mut size_cache = size_cache_mutex.lock().unwrap();
+        size_cache.retain(|(dir, _), _| !dir.starts_with(path));
+    }
+
     /// format a number of bytes as a string
     /// (probably fast enough but not benchmarked)
     pub fn to_string(&self) -> String {
@@ -108,3 +132,22 @@ impl Into<u64> for Size {
         self.0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dirs::TestDir;
+
+    #[test]
+    fn directories_on_another_device_are_not_measured() {
+        let dir = TestDir::new("file-sizes-dev");
+        let sub = dir.dir("sub");
+        dir.file("sub/file", &[0; 1000]);
+        let dev = fs::metadata(&sub).unwrap().dev();
+        let tl = TaskLifetime::unlimited();
+        let size = |root_dev| Size::from_dir(&sub, root_dev, &tl);
+        assert_eq!(size(None), Some(Size::from(1000)));
+        assert_eq!(size(Some(dev)), Some(Size::from(1000)));
+        assert_eq!(size(Some(dev + 1)), Some(Size::from(0)));
+    }
+}
@@ -0,0 +1,266 @@
+//! a filter selects files according to their metadata instead
+//!  of their name. Filters are typed in the input like patterns
+//!  and can be combined with them using operators:
+//!  - `>10M` or `size<2k` : files bigger or smaller than a size
+//!  - `mtime<2d` or `mtime>1w` : files modified more recently or
+//!    longer ago than a duration
+//!  - `type:dir`, `type:file` or `type:link`
+//!  - `ext:rs` : files with a given extension
+
+use std::fs;
+use std::time::{Duration, SystemTime};
+
+use crate::errors::PatternError;
+use crate::patterns::{Candidate, Match};
+
+// score of a match by a filter, which says nothing about relevance
+//  (it doesn't make the line a better match than another one)
+const SCORE_FILTER: i32 = 0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    Less,
+    Greater,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FileKind {
+    Dir,
+    File,
+    Link,
+}
+
+#[derive(Debug, Clone)]
+pub enum Filter {
+    Size(Comparison, u64),       // in bytes, only applied to files
+    Mtime(Comparison, Duration), // age of the last modification
+    Type(FileKind),
+    Ext(String), // lowercase, without the dot
+}
+
+// tell whether the token is a valid filter using ':' as separator
+//  (e.g. `type:dir`), in which case the ':' isn't a verb separator
+pub fn is_colon_filter(s: &str) -> bool {
+    s.contains(':') && matches!(Filter::from(s), Some(Ok(_)))
+}
+
+impl Filter {
+    // return the filter described by raw, None if raw doesn't look
+    //  like a filter or an error if it looks like one but is invalid
+    pub fn from(raw: &str) -> Option<Result<Filter, PatternError>> {
+        if let Some(value) = raw.strip_prefix("type:") {
+            return Some(match value {
+                "dir" | "d" => Ok(Filter::Type(FileKind::Dir)),
+                "file" | "f" => Ok(Filter::Type(FileKind::File)),
+                "link" | "l" => Ok(Filter::Type(FileKind::Link)),
+                _ => Err(invalid(raw)),
+            });
+        }
+        if let Some(value) = raw.strip_prefix("ext:") {
+            return Some(match value.trim_start_matches('.') {
+                "" => Err(invalid(raw)),
+                ext => Ok(Filter::Ext(ext.to_lowercase())),
+            });
+        }
+        if let Some(value) = raw.strip_prefix("mtime") {
+            let (cmp, value) = comparison(value)?;
+            return Some(match parse_duration(value) {
+                Some(duration) => Ok(Filter::Mtime(cmp, duration)),
+                None => Err(invalid(raw)),
+            });
+        }
+        let (cmp, value) = comparison(raw.strip_prefix("size").unwrap_or(raw))?;
+        Some(match parse_size(value) {
+            Some(size) => Ok(Filter::Size(cmp, size)),
+            None => Err(invalid(raw)),
+        })
+    }
+    // tell whether the filter needs the metadata of the candidate
+    pub fn needs_metadata(&self) -> bool {
+        !matches!(self, Filter::Ext(_))
+    }
+    // return a match (without matching chars) if the candidate passes
+    //  the filter. A filter needing metadata never matches a candidate
+    //  without metadata.
+    pub fn test(&self, candidate: &Candidate<'_>) -> Option<Match> {
+        let ok = match self {
+            Filter::Ext(ext) => match candidate.name.rfind('.') {
+                Some(idx) if idx > 0 => candidate.name[idx + 1..].to_lowercase() == *ext,
+                _ => false,
+            },
+            _ => match candidate.metadata {
+                Some(md) => self.test_metadata(md),
+                None => false,
+            },
+        };
+        if ok {
+            Some(Match {
+                score: SCORE_FILTER,
+                pos: Vec::new(),
+            })
+        } else {
+            None
+        }
+    }
+    fn test_metadata(&self, md: &fs::Metadata) -> bool {
+        match self {
+            Filter::Size(cmp, size) => md.is_file() && compare(*cmp, md.len(), *size),
+            Filter::Mtime(cmp, duration) => {
+                let age = md
+                    .modified()
+                    .ok()
+                    .and_then(|mtime| SystemTime::now().duration_since(mtime).ok())
+                    .unwrap_or_default(); // modified in the future
+                compare(*cmp, age, *duration)
+            }
+            Filter::Type(kind) => {
+                let ft = md.file_type();
+                match kind {
+                    FileKind::Dir => ft.is_dir(),
+                    FileKind::File => ft.is_file(),
+                    FileKind::Link => ft.is_symlink(),
+                }
+            }
+            Filter::Ext(_) => true, // not a metadata filter
+        }
+    }
+}
+
+fn invalid(raw: &str) -> PatternError {
+    PatternError::InvalidFilter {
+        txt: raw.to_string(),
+    }
+}
+
+fn compare<T: PartialOrd>(cmp: Comparison, value: T, reference: T) -> bool {
+    match cmp {
+        Comparison::Less => value < reference,
+        Comparison::Greater => value > reference,
+    }
+}
+
+fn comparison(s: &str) -> Option<(Comparison, &str)> {
+    if let Some(value) = s.strip_prefix('<') {
+        Some((Comparison::Less, value))
+    } else {
+        s.strip_prefix('>')
+            .map(|value| (Comparison::Greater, value))
+    }
+}
+
+// split a string like "10M" or "2d" into its number and its unit
+fn number_and_unit(s: &str) -> Option<(u64, &str)> {
+    let unit_start = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let number = s[..unit_start].parse().ok()?;
+    Some((number, &s[unit_start..]))
+}
+
+// parse a size like "300", "10k" or "2GB". Units are powers
+//  of 1024, like the sizes displayed in the tree
+fn parse_size(s: &str) -> Option<u64> {
+    let (number, unit) = number_and_unit(s)?;
+    let unit = unit.to_lowercase();
+    let power = match unit.strip_suffix('b').unwrap_or(&unit) {
+        "" => 0,
+        "k" => 1,
+        "m" => 2,
+        "g" => 3,
+        "t" => 4,
+        _ => {
+            return None;
+        }
+    };
+    number.checked_mul(1024u64.pow(power))
+}
+
+// parse a duration like "30m", "2d" or "1w". The unit is mandatory
+fn parse_duration(s: &str) -> Option<Duration> {
+    let (number, unit) = number_and_unit(s)?;
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        "y" => 365 * 24 * 60 * 60,
+        _ => {
+            return None;
+        }
+    };
+    number.checked_mul(seconds).map(Duration::from_secs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes_are_in_powers_of_1024() {
+        assert_eq!(parse_size("300"), Some(300));
+        assert_eq!(parse_size("10k"), Some(10 * 1024));
+        assert_eq!(parse_size("2MB"), Some(2 * 1024 * 1024));
+        assert_eq!(parse_size("1g"), Some(1024 * 1024 * 1024));
+        assert_eq!(parse_size("3T"), Some(3 * 1024u64.pow(4)));
+        assert_eq!(parse_size("10x"), None);
+        assert_eq!(parse_size("k"), None);
+        assert_eq!(parse_size(""), None);
+        assert_eq!(parse_size("99999999999T"), None); // overflow
+    }
+
+    #[test]
+    fn durations_need_a_unit() {
+        assert_eq!(parse_duration("30s"), Some(Duration::from_secs(30)));
+        assert_eq!(parse_duration("30m"), Some(Duration::from_secs(30 * 60)));
+        assert_eq!(
+            parse_duration("2d"),
+            Some(Duration::from_secs(2 * 24 * 3600))
+        );
+        assert_eq!(
+            parse_duration("1w"),
+            Some(Duration::from_secs(7 * 24 * 3600))
+        );
+        assert_eq!(
+            parse_duration("1y"),
+            Some(Duration::from_secs(365 * 24 * 3600))
+        );
+        assert_eq!(parse_duration("30"), None);
+        assert_eq!(parse_duration("2D"), None);
+        assert_eq!(parse_duration("d"), None);
+    }
+
+    #[test]
+    fn filters_are_recognized() {
+        assert!(matches!(
+            Filter::from(">10M"),
+            Some(Ok(Filter::Size(Comparison::Greater, 10_485_760)))
+        ));
+        assert!(matches!(
+            Filter::from("size<2k"),
+            Some(Ok(Filter::Size(Comparison::Less, 2048)))
+        ));
+        assert!(matches!(
+            Filter::from("mtime<2d"),
+            Some(Ok(Filter::Mtime(Comparison::Less, _)))
+        ));
+        assert!(matches!(
+            Filter::from("type:d"),
+            Some(Ok(Filter::Type(FileKind::Dir)))
+        ));
+        assert!(matches!(Filter::from("ext:.RS"), Some(Ok(Filter::Ext(ext))) if ext == "rs"));
+        assert!(matches!(Filter::from("mtime>2"), Some(Err(_))));
+        assert!(matches!(Filter::from("type:e"), Some(Err(_))));
+        assert!(Filter::from("main").is_none());
+        assert!(Filter::from("mtime").is_none());
+    }
+
+    #[test]
+    fn only_valid_colon_filters_keep_their_colon() {
+        assert!(is_colon_filter("type:dir"));
+        assert!(is_colon_filter("ext:rs"));
+        assert!(!is_colon_filter("type:"));
+        assert!(!is_colon_filter("type:e"));
+        assert!(!is_colon_filter("ext:"));
+        assert!(!is_colon_filter("main:e"));
+        assert!(!is_colon_filter("type"));
+    }
+}
@@ -1,21 +1,62 @@
 //! in the flat_tree structure, every "node" is just a line, there's
 //!  no link from a child to its parent or from a parent to its children.
 
-use std::cmp::{self, Ordering};
-use std::path::PathBuf;
+use std::cmp::{self, Ordering, Reverse};
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
 
 use crate::file_sizes::Size;
+use crate::patterns::Match;
 use crate::task_sync::TaskLifetime;
-use crate::tree_options::TreeOptions;
+use crate::tree_options::{SortOrder, TreeOptions};
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum LineType {
     File,
+    Executable, // a file with an execution permission
+    Fifo,       // a named pipe
+    Socket,
+    BlockDevice,
+    CharDevice,
     Dir,
-    SymLink(String), // store the lineType of destination ?
+    SymLink(String, TargetType), // the target, as written in the link, and its type
+    SymLinkToDir(String),        // a link to a directory, followed like a directory
     Pruning,
 }
 
+// what a symbolic link points to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TargetType {
+    File,
+    Dir,
+    Link,   // another link, whose chain ends with an existing file or dir
+    Broken, // the target, or the end of the chain, doesn't exist
+}
+
+impl TargetType {
+    // the type of the target of the link at the given path
+    pub fn of(link_path: &Path, target: &Path) -> TargetType {
+        let md = match fs::metadata(link_path) {
+            Ok(md) => md,
+            Err(_) => {
+                return TargetType::Broken;
+            }
+        };
+        let target_path = match link_path.parent() {
+            Some(parent) => parent.join(target),
+            None => target.to_path_buf(),
+        };
+        if fs::symlink_metadata(target_path).is_ok_and(|md| md.file_type().is_symlink()) {
+            TargetType::Link
+        } else if md.is_dir() {
+            TargetType::Dir
+        } else {
+            TargetType::File
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct TreeLine {
     pub left_branchs: Box<[bool]>,
@@ -25,8 +66,11 @@ pub struct TreeLine {
     pub line_type: LineType,
     pub has_error: bool,
     pub unlisted: usize, // number of not listed children (Dir) or brothers (Pruning)
-    pub score: i32,      // 0 if there's no pattern
+    pub score: i32,      // 0 if there's no pattern or no relevant match
+    pub direct_match: bool, // true when the line itself matches the pattern
+    pub name_match: Option<Match>, // the chars to highlight in the name
     pub size: Option<Size>, // None when not measured
+    pub mtime: i64,      // last modification, in seconds since the epoch
     pub mode: u32,       // unix file mode
     pub uid: u32,        // unix user id
     pub gid: u32,        // unix group id
@@ -37,29 +81,94 @@ pub struct Tree {
     pub lines: Box<[TreeLine]>,
     pub selection: usize, // there's always a selection (starts with root, which is 0)
     pub options: TreeOptions,
-    pub scroll: i32,        // FIXME usize
-    pub nb_gitignored: u32, // number of times a gitignore pattern excluded a file
+    pub scroll: i32,                     // FIXME usize
+    pub nb_gitignored: u32,              // number of times a gitignore pattern excluded a file
+    pub approximate: bool,               // true when the matches tolerate typos
+    pub match_count: Option<MatchCount>, // only for total searches
+}
+
+// the number of matches of a search of the whole tree
+#[derive(Debug, Clone, Copy)]
+pub struct MatchCount {
+    pub nb_matches: usize,
+    pub nb_dirs: usize, // number of directories containing matches
+    pub complete: bool, // false while the search goes on
 }
 
 impl TreeLine {
-    pub fn is_selectable(&self) -> bool {
-        match &self.line_type {
-            LineType::Pruning => false,
-            _ => true,
-        }
+    // a pruning line stands for the unlisted children of its parent.
+    // It can be selected, to expand the parent.
+    pub fn is_pruning(&self) -> bool {
+        self.line_type == LineType::Pruning
     }
     pub fn is_dir(&self) -> bool {
         match &self.line_type {
-            LineType::Dir => true,
+            LineType::Dir | LineType::SymLinkToDir(_) => true,
             _ => false,
         }
     }
     pub fn is_file(&self) -> bool {
         match &self.line_type {
-            LineType::File => true,
+            LineType::File | LineType::Executable => true,
             _ => false,
         }
     }
+    // true when the name of the file isn't valid UTF-8, so that
+    //  it's displayed with replacement chars
+    pub fn has_lossy_name(&self) -> bool {
+        self.path
+            .file_name()
+            .is_some_and(|name| name.to_str().is_none())
+    }
+    // the path a link points to (which may not exist), relative to
+    //  the directory of the link if the link's target is relative
+    pub fn link_target(&self) -> Option<PathBuf> {
+        let target = match &self.line_type {
+            LineType::SymLink(target, _) | LineType::SymLinkToDir(target) => target,
+            _ => {
+                return None;
+            }
+        };
+        Some(match self.path.parent() {
+            Some(parent) => parent.join(target),
+            None => PathBuf::from(target),
+        })
+    }
+    // the kind of the file when it's neither a regular file nor a
+    //  directory or a link. Such a file can't be opened, as reading
+    //  it may block or make no sense
+    pub fn special_kind(&self) -> Option<&'static str> {
+        match &self.line_type {
+            LineType::Fifo => Some("named pipe"),
+            LineType::Socket => Some("socket"),
+            LineType::BlockDevice => Some("block device"),
+            LineType::CharDevice => Some("character device"),
+            _ => None,
+        }
+    }
+    // compare two lines having the same parent, according to the sort
+    //  order. A pruning line stays after its brothers.
+    fn cmp_brothers(&self, other: &TreeLine, sort: SortOrder) -> Ordering {
+        let is_pruning = |line: &TreeLine| line.line_type == LineType::Pruning;
+        is_pruning(self)
+            .cmp(&is_pruning(other))
+            .then_with(|| match sort {
+                SortOrder::Name => Ordering::Equal,
+                SortOrder::Size => other.size.cmp(&self.size),
+                SortOrder::Date => other.mtime.cmp(&self.mtime),
+                SortOrder::Extension => extension(&self.name).cmp(&extension(&other.name)),
+                SortOrder::Type => other.is_dir().cmp(&self.is_dir()),
+            })
+            .then_with(|| self.cmp(other))
+    }
+}
+
+// the lowercase extension of a file name, empty if there's none
+pub fn extension(name: &str) -> String {
+    match name.rfind('.') {
+        Some(idx) if idx > 0 => name[idx + 1..].to_lowercase(),
+        _ => String::new(),
+    }
 }
 impl PartialEq for TreeLine {
     fn eq(&self, other: &TreeLine) -> bool {
@@ -121,14 +230,21 @@ impl Tree {
     pub fn after_lines_changed(&mut self) {
         // we sort the lines
         self.lines.sort();
-
+        if self.options.sort != SortOrder::Name {
+            self.sort_brothers();
+        }
+        self.update_branches();
+    }
+    // compute the left branchs (for the drawing) and mark the last
+    //  children as pruning, if they have unlisted brothers.
+    // As the unlisted brothers are then counted by the pruning line,
+    //  this can be done again after the brothers are reordered.
+    fn update_branches(&mut self) {
         for i in 1..self.lines.len() {
             for d in 0..self.lines[i].left_branchs.len() {
                 self.lines[i].left_branchs[d] = false;
             }
         }
-        // then we discover the branches (for the drawing)
-        // and we mark the last children as pruning, if they have unlisted brothers
         let mut last_parent_index: usize = self.lines.len() + 1;
         for end_index in (1..self.lines.len()).rev() {
             let depth = (self.lines[end_index].depth - 1) as usize;
@@ -158,6 +274,9 @@ impl Tree {
                     if unlisted > 0 {
                         self.lines[end_index].line_type = LineType::Pruning;
                         self.lines[end_index].unlisted = unlisted + 1;
+                        self.lines[end_index].score = 0; // its match isn't visible
+                        self.lines[end_index].direct_match = false;
+                        self.lines[end_index].name_match = None;
                         self.lines[parent_index].unlisted = 0;
                     }
                     last_parent_index = parent_index;
@@ -169,6 +288,54 @@ impl Tree {
             }
         }
     }
+    // reorder the lines, which must be sorted by path (or already
+    //  grouped by parent), so that the children of every directory
+    //  follow the sort order
+    fn sort_brothers(&mut self) {
+        // as lines are sorted by path, the parent of a line is the last
+        //  line before it with a lesser depth
+        let mut children: Vec<Vec<usize>> = vec![Vec::new(); self.lines.len()];
+        let mut ancestors: Vec<usize> = vec![0];
+        for idx in 1..self.lines.len() {
+            let depth = self.lines[idx].depth as usize;
+            ancestors.truncate(depth);
+            if let Some(&parent_idx) = ancestors.last() {
+                children[parent_idx].push(idx);
+            }
+            ancestors.push(idx);
+        }
+        let sort = self.options.sort;
+        for brothers in children.iter_mut() {
+            brothers.sort_by(|&a, &b| self.lines[a].cmp_brothers(&self.lines[b], sort));
+        }
+        // we walk the tree to get the new order
+        let mut order = Vec::with_capacity(self.lines.len());
+        let mut stack = vec![0];
+        while let Some(idx) = stack.pop() {
+            order.push(idx);
+            stack.extend(children[idx].iter().rev());
+        }
+        let mut lines: Vec<Option<TreeLine>> = std::mem::take(&mut self.lines)
+            .into_vec()
+            .into_iter()
+            .map(Some)
+            .collect();
+        self.lines = order
+            .into_iter()
+            .filter_map(|idx| lines[idx].take())
+            .collect();
+    }
+    // once all sizes are known, sort the lines again if they're
+    //  sorted by size, keeping the selection
+    fn sort_by_size_if_complete(&mut self) {
+        if self.options.sort != SortOrder::Size || self.has_dir_missing_size() {
+            return;
+        }
+        let selected_path = self.selected_line().path.clone();
+        self.sort_brothers(); // the pruning lines stay at the end
+        self.update_branches();
+        self.try_select_path(&selected_path);
+    }
     pub fn has_branch(&self, line_index: usize, depth: usize) -> bool {
         if line_index >= self.lines.len() {
             return false;
@@ -182,12 +349,7 @@ impl Tree {
     pub fn move_selection(&mut self, dy: i32, page_height: i32) {
         // only work for +1 or -1
         let l = self.lines.len();
-        loop {
-            self.selection = (self.selection + ((l as i32) + dy) as usize) % l;
-            if self.lines[self.selection].is_selectable() {
-                break;
-            }
-        }
+        self.selection = (self.selection + ((l as i32) + dy) as usize) % l;
         // we adjust the scroll
         let l = l as i32;
         let sel = self.selection as i32;
@@ -204,14 +366,7 @@ impl Tree {
     pub fn select_visible_line(&mut self, page_height: i32) {
         let sel = self.selection as i32;
         if sel < self.scroll || sel >= self.scroll + page_height {
-            self.selection = self.scroll as usize;
-            let l = self.lines.len();
-            loop {
-                self.selection = (self.selection + ((l as i32) + 1) as usize) % l;
-                if self.lines[self.selection].is_selectable() {
-                    break;
-                }
-            }
+            self.selection = (self.scroll as usize + 1) % self.lines.len();
         }
     }
     pub fn make_selection_visible(&mut self, page_height: i32) {
@@ -229,23 +384,50 @@ impl Tree {
     pub fn root(&self) -> &PathBuf {
         &self.lines[0].path
     }
-    // select the line with the best matching score
-    pub fn try_select_best_match(&mut self) {
-        let mut best_score = 0;
+    // tell whether the given directory is a line of the tree
+    pub fn has_dir(&self, path: &Path) -> bool {
+        self.lines
+            .iter()
+            .any(|line| line.path == path && line.is_dir())
+    }
+    // select the line with the given path, if it's in the tree
+    pub fn try_select_path(&mut self, path: &Path) -> bool {
         for (idx, line) in self.lines.iter().enumerate() {
-            if !line.is_selectable() {
-                continue;
+            if line.path == path && !line.is_pruning() {
+                self.selection = idx;
+                return true;
+            }
+        }
+        false
+    }
+    // select the line with the given path or, if it's not in the
+    //  tree, its closest ancestor (at worst the root)
+    pub fn select_path_or_ancestor(&mut self, path: &Path) {
+        for ancestor in path.ancestors() {
+            if self.try_select_path(ancestor) {
+                return;
             }
-            if best_score > line.score {
+        }
+        self.selection = 0;
+    }
+    // select the matching line with the best score
+    pub fn try_select_best_match(&mut self) {
+        let mut best: Option<usize> = None;
+        for (idx, line) in self.lines.iter().enumerate() {
+            if !line.direct_match {
                 continue;
             }
-            if line.score == best_score {
+            if let Some(best_idx) = best {
+                let best_line = &self.lines[best_idx];
                 // in case of equal scores, we prefer the shortest path
-                if self.lines[idx].depth >= self.lines[self.selection].depth {
+                if (line.score, Reverse(line.depth)) <= (best_line.score, Reverse(best_line.depth))
+                {
                     continue;
                 }
             }
-            best_score = line.score;
+            best = Some(idx);
+        }
+        if let Some(idx) = best {
             self.selection = idx;
         }
     }
@@ -253,10 +435,7 @@ impl Tree {
         for di in 0..self.lines.len() {
             let idx = (self.selection + di + 1) % self.lines.len();
             let line = &self.lines[idx];
-            if !line.is_selectable() {
-                continue;
-            }
-            if line.score > 0 {
+            if line.direct_match {
                 self.selection = idx;
                 return true;
             }
@@ -280,11 +459,20 @@ impl Tree {
                 self.lines[i].size = Some(Size::from_file(&self.lines[i].path));
             }
         }
+        self.sort_by_size_if_complete();
     }
     pub fn fetch_some_missing_dir_size(&mut self, tl: &TaskLifetime) {
+        // the device of the root, when sizes must not cross mount points
+        let root_dev = match self.options.one_filesystem {
+            true => fs::metadata(self.root()).ok().map(|md| md.dev()),
+            false => None,
+        };
         for i in 1..self.lines.len() {
             if self.lines[i].size.is_none() && self.lines[i].is_dir() {
-                self.lines[i].size = Size::from_dir(&self.lines[i].path, tl);
+                self.lines[i].size = Size::from_dir(&self.lines[i].path, root_dev, tl);
+                if self.lines[i].size.is_some() {
+                    self.sort_by_size_if_complete();
+                }
                 return;
             }
         }
@@ -308,3 +496,42 @@ impl Tree {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dirs::TestDir;
+    use crate::tree_build::TreeBuilder;
+
+    fn build(dir: &TestDir, options: TreeOptions) -> Tree {
+        let mut builder = TreeBuilder::from(dir.path.clone(), options, 100);
+        builder.resumable_build(&TaskLifetime::unlimited()).unwrap()
+    }
+
+    #[test]
+    fn displayed_directories_are_known() {
+        // it's what decides which expanded directories are remembered
+        let dir = TestDir::new("flat-tree-has-dir");
+        dir.file("sub/file", b"");
+        let tree = build(&dir, TreeOptions::new());
+        assert!(tree.has_dir(&dir.path.join("sub")));
+        assert!(!tree.has_dir(&dir.path.join("sub/file")));
+        assert!(!tree.has_dir(&dir.path.join("gone")));
+    }
+
+    #[test]
+    fn chains_of_links_can_be_followed() {
+        // what the :jump verb does, one link at a time
+        let dir = TestDir::new("flat-tree-link-chain");
+        let file = dir.file("sub/file", b"");
+        std::os::unix::fs::symlink("file", dir.path.join("sub/last")).unwrap();
+        std::os::unix::fs::symlink("sub/last", dir.path.join("first")).unwrap();
+        let tree = build(&dir, TreeOptions::new());
+        let line = |path: &Path| tree.lines.iter().find(|l| l.path == path).unwrap();
+        let target = line(&dir.path.join("first")).link_target().unwrap();
+        assert_eq!(target, dir.path.join("sub/last"));
+        let target = line(&target).link_target().unwrap();
+        assert_eq!(target, file);
+        assert_eq!(line(&target).link_target(), None);
+    }
+}
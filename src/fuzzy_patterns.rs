@@ -0,0 +1,512 @@
+//! a trivial fuzzy pattern matcher for filename filtering / sorting
+//! It's not meant for file contents but for small strings (less than 1000 chars)
+//!  such as file names or paths relative to the tree root.
+//! Both the pattern and the candidates are normalized (see the normalization
+//!  module) so that matching is case insensitive for all letters (unless the
+//!  pattern is case sensitive) and ignores diacritics unless the pattern
+//!  contains some.
+//! Among the possible alignments of the pattern in the candidate, the
+//!  best one is chosen: compact and with chars at the start of words
+//!  (after a separator or at a camelCase hump).
+//! A pattern may also be made tolerant to typos, in which case some
+//!  chars may be mistyped or swapped with the next one, at a cost.
+
+use crate::normalization::{self, NormalizedChars};
+use crate::patterns::Match;
+
+const MAX_LENGTH_BASE: usize = 2;
+const MAX_LENGTH_PER_CHAR: usize = 2;
+const MAX_TYPOS: usize = 2;
+const MIN_CHARS_PER_TYPO: usize = 3; // so that very short patterns don't match everything
+
+/// The weights used in match score computing. They can be
+///  changed in the [fuzzy_scoring] section of the configuration
+#[derive(Debug, Clone, Copy)]
+pub struct FuzzyWeights {
+    pub bonus_match: i32,            // base score of any match
+    pub bonus_exact: i32,            // when the pattern is the whole name
+    pub bonus_start: i32,            // when the first char of the name matches
+    pub bonus_word_start: i32,       // per matching char following a separator (e.g. `_` or `.`)
+    pub bonus_camel_hump: i32,       // per matching uppercase char following a lowercase one
+    pub bonus_candidate_length: i32, // per char of the name
+    pub bonus_length: i32,           // per char of the span of the match
+    pub bonus_last_component: i32,   // per char matched in the last component of a path
+    pub bonus_typo: i32,             // per typo, when tolerating typos
+}
+
+impl Default for FuzzyWeights {
+    fn default() -> FuzzyWeights {
+        FuzzyWeights {
+            bonus_match: 10_000,
+            bonus_exact: 1_000,
+            bonus_start: 30,
+            bonus_word_start: 50,
+            bonus_camel_hump: 40,
+            bonus_candidate_length: -1,
+            bonus_length: -10,
+            bonus_last_component: 50,
+            bonus_typo: -1_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FuzzyPattern {
+    lc_chars: Box<[char]>, // normalized characters
+    case_sensitive: bool,
+    keep_diacritics: bool, // true when the pattern contains diacritics
+    weights: FuzzyWeights,
+    max_typos: usize, // 0 unless typos are tolerated
+}
+
+// a step of the search of the best alignment: a char of the
+//  pattern is matched at the pos in the candidate
+struct Step {
+    pos: usize,
+    score: i32,          // score of the alignment up to this char
+    prev: Option<usize>, // index of the step of the previous char
+}
+
+impl FuzzyPattern {
+    pub fn from(pat: &str, case_sensitive: bool, weights: FuzzyWeights) -> FuzzyPattern {
+        let normalized = NormalizedChars::from(pat, !case_sensitive, true);
+        let keep_diacritics = normalized
+            .chars
+            .iter()
+            .any(|&c| normalization::is_diacritic(c));
+        let lc_chars = normalized.chars.into_boxed_slice();
+        FuzzyPattern {
+            lc_chars,
+            case_sensitive,
+            keep_diacritics,
+            weights,
+            max_typos: 0,
+        }
+    }
+    // return a copy of the pattern tolerating a few mistyped or swapped chars,
+    //  or None if the pattern is too short for that
+    pub fn with_typos(&self) -> Option<FuzzyPattern> {
+        let max_typos = MAX_TYPOS.min(self.lc_chars.len() / MIN_CHARS_PER_TYPO);
+        if max_typos == 0 {
+            return None;
+        }
+        let mut tolerant = self.clone();
+        tolerant.max_typos = max_typos;
+        Some(tolerant)
+    }
+    // compute the bonus of every normalized char of the candidate,
+    //  would it be matched
+    fn char_bonuses(&self, candidate: &str, normalized: &NormalizedChars) -> Vec<i32> {
+        let orig_chars: Vec<char> = candidate.chars().collect();
+        let mut bonuses = vec![0; normalized.chars.len()];
+        for (idx, bonus) in bonuses.iter_mut().enumerate() {
+            let orig_idx = normalized.orig_idx[idx];
+            if idx > 0 && normalized.orig_idx[idx - 1] == orig_idx {
+                continue; // not the first char of a decomposed one
+            }
+            if orig_idx == 0 {
+                *bonus = self.weights.bonus_start + self.weights.bonus_word_start;
+                continue;
+            }
+            let prev = orig_chars[orig_idx - 1];
+            let c = orig_chars[orig_idx];
+            if !prev.is_alphanumeric() {
+                *bonus = self.weights.bonus_word_start;
+            } else if prev.is_lowercase() && c.is_uppercase() {
+                *bonus = self.weights.bonus_camel_hump;
+            }
+        }
+        bonuses
+    }
+    // find the best alignment of the pattern in the candidate chars, with
+    //  a span of at most max_match_len and ending at or after min_end.
+    // Returns the score of the alignment (sum of the char bonuses, of the
+    //  span penalty and of the typo penalty) and the positions of the
+    //  matching chars.
+    // An alignment with fewer typos is always preferred.
+    // When the span is limited, the search is done in a window for every
+    //  possible start, which costs O(window * pattern * typos) each. Without
+    //  typos, the only possible starts are the occurrences of the first char
+    //  of the pattern, and only when the candidate contains all its chars.
+    fn best_alignment(
+        &self,
+        cand_chars: &[char],
+        bonuses: &[i32],
+        max_match_len: usize,
+        min_end: usize,
+    ) -> Option<(i32, Vec<usize>)> {
+        let nb_cand_chars = cand_chars.len();
+        if self.max_typos == 0 && !contains_in_order(cand_chars, &self.lc_chars) {
+            return None;
+        }
+        let best = if max_match_len >= nb_cand_chars {
+            // the span isn't limited, the alignment may start anywhere
+            self.best_alignment_in(cand_chars, bonuses, 0, nb_cand_chars, false, min_end)
+        } else {
+            // the alignments starting at a given position are compared between
+            //  themselves, in the window in which their span is acceptable
+            let mut best: Option<(usize, i32, Vec<usize>)> = None;
+            for start in 0..nb_cand_chars {
+                if self.max_typos == 0 && cand_chars[start] != self.lc_chars[0] {
+                    continue;
+                }
+                let end = nb_cand_chars.min(start + max_match_len + 1);
+                let alignment =
+                    match self.best_alignment_in(cand_chars, bonuses, start, end, true, min_end) {
+                        Some(alignment) => alignment,
+                        None => continue,
+                    };
+                let better = match &best {
+                    Some((nb_typos, score, _)) => {
+                        alignment.0 < *nb_typos
+                            || (alignment.0 == *nb_typos && alignment.1 > *score)
+                    }
+                    None => true,
+                };
+                if better {
+                    best = Some(alignment);
+                }
+            }
+            best
+        };
+        best.map(|(nb_typos, score, pos)| (score + self.weights.bonus_typo * nb_typos as i32, pos))
+    }
+    // find the best alignment of the pattern in the candidate chars whose
+    //  positions are in [first, end[ (starting at first when anchored) and
+    //  ending at or after min_end.
+    // Returns the number of typos, the score without the typo penalty, and
+    //  the positions of the matching chars.
+    // The state of the search is the number of typos, the index of the last
+    //  aligned char of the pattern and its position in the candidate. A typo
+    //  is either a char of the pattern matched by any other char (but '/'),
+    //  or a char of the pattern found swapped with the next one.
+    fn best_alignment_in(
+        &self,
+        cand_chars: &[char],
+        bonuses: &[i32],
+        first: usize,
+        end: usize,
+        anchored: bool,
+        min_end: usize,
+    ) -> Option<(usize, i32, Vec<usize>)> {
+        let pat = &self.lc_chars;
+        let width = end - first;
+        let bonus_length = self.weights.bonus_length;
+        // all the steps, linked to their previous one
+        let mut steps: Vec<Step> = Vec::new();
+        // aligned[nb_typos][pos - first] is the best step, if any, ending the alignment
+        //  of the chars of the pattern up to the current one at pos
+        let mut aligned: Vec<Vec<Option<usize>>> = Vec::new();
+        // swapped[nb_typos][pos - first] is the best step, if any, where the char
+        //  following the current one was found at pos, before the current one
+        let mut swapped: Vec<Vec<Option<usize>>> = Vec::new();
+        for pat_idx in 0..pat.len() {
+            // the score and the previous step of a new step at pos, knowing the best
+            //  previous step with prev_typos typos (or, for the first char of the
+            //  pattern, if it's a start without typo)
+            let next = |prev: Option<(i32, usize)>, prev_typos: Option<usize>, pos, bonus| {
+                if pat_idx == 0 {
+                    let is_start = prev_typos == Some(0) && (!anchored || pos == first);
+                    return if is_start { Some((bonus, None)) } else { None };
+                }
+                prev.map(|(value, prev)| (value + bonus_length * pos as i32 + bonus, Some(prev)))
+            };
+            let mut new_aligned = vec![vec![None; width]; self.max_typos + 1];
+            let mut new_swapped = vec![vec![None; width]; self.max_typos + 1];
+            for nb_typos in 0..=self.max_typos {
+                let fewer_typos = nb_typos.checked_sub(1);
+                let exact = best_before(aligned.get(nb_typos), &steps, bonus_length, width);
+                let substituted = best_before(
+                    fewer_typos.and_then(|t| aligned.get(t)),
+                    &steps,
+                    bonus_length,
+                    width,
+                );
+                let unswapped = best_before(
+                    fewer_typos.and_then(|t| swapped.get(t)),
+                    &steps,
+                    bonus_length,
+                    width,
+                );
+                let may_swap = pat_idx + 1 < pat.len() && pat[pat_idx] != pat[pat_idx + 1];
+                for i in 0..width {
+                    let pos = first + i;
+                    let c = cand_chars[pos];
+                    let mut best: Option<(i32, Option<usize>)> = None;
+                    let mut consider = |step: Option<(i32, Option<usize>)>| {
+                        if let Some(step) = step {
+                            if best.is_none_or(|b| step.0 > b.0) {
+                                best = Some(step);
+                            }
+                        }
+                    };
+                    if c == pat[pat_idx] {
+                        consider(next(exact[i], Some(nb_typos), pos, bonuses[pos]));
+                    } else if nb_typos > 0 && c != '/' {
+                        // a substituted char doesn't get the bonus of its position
+                        consider(next(substituted[i], fewer_typos, pos, 0));
+                    }
+                    if pat_idx > 0 && c == pat[pat_idx - 1] {
+                        consider(next(unswapped[i], fewer_typos, pos, bonuses[pos]));
+                    }
+                    if let Some((score, prev)) = best {
+                        steps.push(Step { pos, score, prev });
+                        new_aligned[nb_typos][i] = Some(steps.len() - 1);
+                    }
+                    if may_swap && c == pat[pat_idx + 1] {
+                        if let Some((score, prev)) =
+                            next(exact[i], Some(nb_typos), pos, bonuses[pos])
+                        {
+                            steps.push(Step { pos, score, prev });
+                            new_swapped[nb_typos][i] = Some(steps.len() - 1);
+                        }
+                    }
+                }
+            }
+            let is_empty =
+                |grid: &Vec<Vec<Option<usize>>>| grid.iter().flatten().all(Option::is_none);
+            if is_empty(&new_aligned) && is_empty(&new_swapped) {
+                return None;
+            }
+            aligned = new_aligned;
+            swapped = new_swapped;
+        }
+        // the first row with an acceptable end is the one with the fewest typos
+        let (nb_typos, best) = aligned.iter().enumerate().find_map(|(nb_typos, row)| {
+            let mut best: Option<&Step> = None;
+            for step in row.iter().flatten().map(|&step_idx| &steps[step_idx]) {
+                if step.pos >= min_end && best.is_none_or(|b| step.score > b.score) {
+                    best = Some(step);
+                }
+            }
+            best.map(|best| (nb_typos, best))
+        })?;
+        let mut pos = Vec::with_capacity(pat.len());
+        let mut step = Some(best);
+        while let Some(s) = step {
+            pos.push(s.pos);
+            step = s.prev.map(|prev_idx| &steps[prev_idx]);
+        }
+        pos.reverse();
+        Some((nb_typos, best.score, pos))
+    }
+    // return a match if the pattern can be found in the candidate string
+    pub fn test(&self, candidate: &str) -> Option<Match> {
+        let normalized =
+            NormalizedChars::from(candidate, !self.case_sensitive, self.keep_diacritics);
+        let cand_chars = &normalized.chars;
+        if self.lc_chars.is_empty() || cand_chars.len() < self.lc_chars.len() {
+            return None;
+        }
+        let max_match_len = MAX_LENGTH_BASE + MAX_LENGTH_PER_CHAR * self.lc_chars.len();
+        let bonuses = self.char_bonuses(candidate, &normalized);
+        let (alignment_score, pos) = self.best_alignment(cand_chars, &bonuses, max_match_len, 0)?;
+        let mut score = self.weights.bonus_match + alignment_score;
+        score += self.weights.bonus_candidate_length * (cand_chars.len() as i32);
+        if pos[0] == 0 && cand_chars.len() == self.lc_chars.len() {
+            score += self.weights.bonus_exact;
+        }
+        Some(Match {
+            score: score.max(1),
+            pos: normalized.orig_positions(&pos),
+        })
+    }
+    // return a match if the pattern can be found in the path, which is
+    //  relative to the tree root and whose components are separated by '/'.
+    // Contrary to test, there's no limit on the length of the match, as
+    //  it commonly spans several directory names, but the match must end
+    //  in the last component (otherwise every descendant of a matching
+    //  directory would match too).
+    pub fn test_path(&self, path: &str) -> Option<Match> {
+        let normalized = NormalizedChars::from(path, !self.case_sensitive, self.keep_diacritics);
+        let cand_chars = &normalized.chars;
+        let nb_pat_chars = self.lc_chars.len();
+        if nb_pat_chars == 0 || cand_chars.len() < nb_pat_chars {
+            return None;
+        }
+        let last_comp_start = match cand_chars.iter().rposition(|&c| c == '/') {
+            Some(idx) => idx + 1,
+            None => 0,
+        };
+        let bonuses = self.char_bonuses(path, &normalized);
+        let (alignment_score, pos) =
+            self.best_alignment(cand_chars, &bonuses, usize::MAX, last_comp_start)?;
+        let mut score = self.weights.bonus_match + alignment_score;
+        score += self.weights.bonus_candidate_length * (cand_chars.len() as i32);
+        let nb_in_last_comp = pos.iter().filter(|&&p| p >= last_comp_start).count();
+        score += self.weights.bonus_last_component * (nb_in_last_comp as i32);
+        if pos[0] == last_comp_start && cand_chars.len() - last_comp_start == nb_pat_chars {
+            score += self.weights.bonus_exact;
+        }
+        Some(Match {
+            score: score.max(1),
+            pos: normalized.orig_positions(&pos),
+        })
+    }
+}
+
+// tell whether all the chars of the pattern are in the candidate, in the same order
+fn contains_in_order(cand_chars: &[char], pat_chars: &[char]) -> bool {
+    let mut cand_chars = cand_chars.iter();
+    pat_chars.iter().all(|pc| cand_chars.any(|cc| cc == pc))
+}
+
+// for every position in a row of the search of the best alignment, the
+//  best step at a previous position, with its score minus the span penalty
+//  from its position (so that the steps are comparable)
+fn best_before(
+    row: Option<&Vec<Option<usize>>>,
+    steps: &[Step],
+    bonus_length: i32,
+    width: usize,
+) -> Vec<Option<(i32, usize)>> {
+    let mut best_before = vec![None; width];
+    let row = match row {
+        Some(row) => row,
+        None => {
+            return best_before;
+        }
+    };
+    let mut best: Option<(i32, usize)> = None;
+    for i in 1..width {
+        if let Some(step_idx) = row[i - 1] {
+            let step = &steps[step_idx];
+            let value = step.score - bonus_length * step.pos as i32;
+            if best.is_none_or(|b| value > b.0) {
+                best = Some((value, step_idx));
+            }
+        }
+        best_before[i] = best;
+    }
+    best_before
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pattern(pat: &str) -> FuzzyPattern {
+        FuzzyPattern::from(pat, false, FuzzyWeights::default())
+    }
+
+    fn pos(pat: &str, candidate: &str) -> Option<Vec<usize>> {
+        pattern(pat).test(candidate).map(|m| m.pos)
+    }
+
+    fn score(pat: &str, candidate: &str) -> i32 {
+        pattern(pat).test(candidate).unwrap().score
+    }
+
+    fn tolerant(pat: &str) -> FuzzyPattern {
+        pattern(pat).with_typos().unwrap()
+    }
+
+    #[test]
+    fn alignments_starting_later_are_found() {
+        // a@0 can't be the start, the span up to c would be too long
+        assert_eq!(pos("abc", "axxxxxabxc"), Some(vec![6, 7, 9]));
+        assert_eq!(pos("abc", "axxxxxxxxxbc"), None);
+    }
+
+    #[test]
+    fn alignments_start_on_the_first_char_of_the_pattern() {
+        assert_eq!(pos("ab", "aaaaaaaaab"), Some(vec![8, 9]));
+        assert_eq!(pos("ab", "xbxa"), None);
+        // with typos, any char may start an alignment
+        assert_eq!(tolerant("xbcd").test("abcd").unwrap().pos, vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn the_span_of_a_name_match_is_limited() {
+        assert_eq!(pos("ab", "axxxxxb"), Some(vec![0, 6]));
+        assert_eq!(pos("ab", "axxxxxxb"), None);
+    }
+
+    #[test]
+    fn compact_matches_are_preferred() {
+        assert_eq!(pos("ab", "xaxbab"), Some(vec![4, 5]));
+        assert!(score("ab", "abxxxx") > score("ab", "axxxxb"));
+    }
+
+    #[test]
+    fn word_starts_are_preferred() {
+        assert_eq!(pos("ba", "xbxx_bar"), Some(vec![5, 6]));
+        assert!(score("bar", "foo_bar") > score("bar", "foobar"));
+        assert!(score("fb", "foo.bar") > score("fb", "foobar"));
+    }
+
+    #[test]
+    fn camel_humps_are_preferred() {
+        assert_eq!(pos("fb", "fbooBar"), Some(vec![0, 4]));
+        assert!(score("fb", "fooBar") > score("fb", "foobar"));
+    }
+
+    #[test]
+    fn paths_matches_end_in_the_last_component() {
+        let p = pattern("srcmain");
+        assert_eq!(
+            p.test_path("src/some/very/long/path/main.rs")
+                .map(|m| m.pos),
+            Some(vec![0, 1, 2, 24, 25, 26, 27])
+        );
+        assert!(p.test_path("src/main/lib.rs").is_none());
+    }
+
+    #[test]
+    fn short_patterns_do_not_tolerate_typos() {
+        assert!(pattern("ab").with_typos().is_none());
+        assert_eq!(tolerant("abc").max_typos, 1);
+        assert_eq!(tolerant("abcdefghijkl").max_typos, MAX_TYPOS);
+    }
+
+    #[test]
+    fn mistyped_and_swapped_chars_are_tolerated() {
+        let p = tolerant("abcdef");
+        assert_eq!(
+            p.test("abxdef").map(|m| m.pos),
+            Some(vec![0, 1, 2, 3, 4, 5])
+        );
+        assert_eq!(
+            p.test("abdcef").map(|m| m.pos),
+            Some(vec![0, 1, 2, 3, 4, 5])
+        );
+        assert_eq!(
+            p.test("bacdxf").map(|m| m.pos),
+            Some(vec![0, 1, 2, 3, 4, 5])
+        );
+        assert!(p.test("axxxef").is_none());
+        assert!(pattern("abcdef").test("abxdef").is_none());
+        // a '/' can't replace a char of the pattern
+        assert!(p.test_path("abc/ef").is_none());
+    }
+
+    #[test]
+    fn typos_are_penalized() {
+        let p = tolerant("abcdef");
+        let score = |candidate: &str| p.test(candidate).unwrap().score;
+        assert_eq!(
+            score("abcdef"),
+            pattern("abcdef").test("abcdef").unwrap().score
+        );
+        assert!(score("abcdef") > score("abxdef"));
+        assert!(score("abxdef") > score("axcxef"));
+        assert!(score("abdcef") > score("badcef"));
+        assert_eq!(
+            score("abxdef") - score("axcxef"),
+            -FuzzyWeights::default().bonus_typo
+        );
+    }
+
+    #[test]
+    fn alignments_without_typo_are_preferred() {
+        let p = tolerant("abcdef");
+        assert_eq!(
+            p.test("abxdef_abcdef").map(|m| m.pos),
+            Some(vec![7, 8, 9, 10, 11, 12])
+        );
+        assert_eq!(
+            p.test_path("abcdxf/abcdef").map(|m| m.pos),
+            Some(vec![7, 8, 9, 10, 11, 12])
+        );
+    }
+}
@@ -0,0 +1,154 @@
+//! a glob pattern, matching the whole name (e.g. `g/*.test.ts`) or,
+//!  when it contains a '/', the whole path relative to the tree root
+//!  (e.g. `g/src/*/mod.rs`)
+
+use crate::errors::PatternError;
+use crate::patterns::Match;
+
+// weights used in match score computing
+const BONUS_MATCH: i32 = 10_000;
+const BONUS_CANDIDATE_LENGTH: i32 = -1; // per char
+
+#[derive(Debug, Clone)]
+pub struct GlobPattern {
+    pattern: glob::Pattern,
+    case_sensitive: bool,
+    literals: Vec<Box<[char]>>, // the parts which aren't wildcards, used for highlighting
+    ends_with_literal: bool,
+}
+
+impl GlobPattern {
+    pub fn from(pat: &str, case_sensitive: bool) -> Result<GlobPattern, PatternError> {
+        let pattern = glob::Pattern::new(pat)?;
+        let mut literals = Vec::new();
+        let mut literal: Vec<char> = Vec::new();
+        let mut ends_with_literal = false;
+        let mut in_class = false;
+        for c in pat.chars() {
+            ends_with_literal = false;
+            match c {
+                ']' if in_class => {
+                    in_class = false;
+                }
+                _ if in_class => {}
+                '*' | '?' | '[' => {
+                    if !literal.is_empty() {
+                        literals.push(literal.into_boxed_slice());
+                        literal = Vec::new();
+                    }
+                    in_class = c == '[';
+                }
+                _ => {
+                    literal.push(c);
+                    ends_with_literal = true;
+                }
+            }
+        }
+        if !literal.is_empty() {
+            literals.push(literal.into_boxed_slice());
+        }
+        Ok(GlobPattern {
+            pattern,
+            case_sensitive,
+            literals,
+            ends_with_literal,
+        })
+    }
+    // tell whether the pattern must be tested on paths instead of names
+    pub fn is_path_pattern(&self) -> bool {
+        self.pattern.as_str().contains('/')
+    }
+    // return a match if the glob matches the whole candidate.
+    // The positions are the ones of the literal parts of the glob.
+    pub fn test(&self, candidate: &str) -> Option<Match> {
+        let options = glob::MatchOptions {
+            case_sensitive: self.case_sensitive,
+            require_literal_separator: true,
+            require_literal_leading_dot: false,
+        };
+        if !self.pattern.matches_with(candidate, &options) {
+            return None;
+        }
+        let cand_chars: Vec<char> = candidate.chars().collect();
+        let same = |a: char, b: char| {
+            a == b || (!self.case_sensitive && a.to_lowercase().eq(b.to_lowercase()))
+        };
+        let found_at = |literal: &[char], start: usize| {
+            literal
+                .iter()
+                .enumerate()
+                .all(|(i, &c)| same(c, cand_chars[start + i]))
+        };
+        let mut pos = Vec::new();
+        let mut cursor = 0;
+        for (idx, literal) in self.literals.iter().enumerate() {
+            if cursor + literal.len() > cand_chars.len() {
+                break;
+            }
+            let start = if idx + 1 == self.literals.len() && self.ends_with_literal {
+                Some(cand_chars.len() - literal.len()).filter(|&start| start >= cursor)
+            } else {
+                (cursor..=cand_chars.len() - literal.len()).find(|&start| found_at(literal, start))
+            };
+            match start {
+                Some(start) => {
+                    pos.extend(start..start + literal.len());
+                    cursor = start + literal.len();
+                }
+                None => break,
+            }
+        }
+        Some(Match {
+            score: BONUS_MATCH + BONUS_CANDIDATE_LENGTH * (cand_chars.len() as i32),
+            pos,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pos(pat: &str, case_sensitive: bool, candidate: &str) -> Option<Vec<usize>> {
+        let pattern = GlobPattern::from(pat, case_sensitive).unwrap();
+        pattern.test(candidate).map(|m| m.pos)
+    }
+
+    #[test]
+    fn literal_parts_are_highlighted() {
+        assert_eq!(
+            pos("*.test.ts", true, "foo.test.ts"),
+            Some((3..11).collect())
+        );
+        assert_eq!(pos("a*b*c", true, "aXbYbc"), Some(vec![0, 2, 5]));
+        assert_eq!(
+            pos("[mn]ain.r?", true, "main.rs"),
+            Some(vec![1, 2, 3, 4, 5])
+        );
+        assert_eq!(pos("*.rs", true, "main.go"), None);
+    }
+
+    #[test]
+    fn a_final_literal_is_highlighted_at_the_end() {
+        assert_eq!(pos("*.rs", true, "main.rs.rs"), Some(vec![7, 8, 9]));
+    }
+
+    #[test]
+    fn case_may_be_ignored() {
+        assert_eq!(pos("*.RS", true, "main.rs"), None);
+        assert_eq!(pos("*.RS", false, "main.rs"), Some(vec![4, 5, 6]));
+    }
+
+    #[test]
+    fn path_patterns_match_the_whole_path() {
+        let pattern = GlobPattern::from("src/*/mod.rs", true).unwrap();
+        assert!(pattern.is_path_pattern());
+        assert_eq!(
+            pattern.test("src/ui/mod.rs").map(|m| m.pos),
+            Some(vec![0, 1, 2, 3, 6, 7, 8, 9, 10, 11, 12])
+        );
+        // a star doesn't match a '/'
+        assert!(pattern.test("src/ui/sub/mod.rs").is_none());
+        assert!(!GlobPattern::from("*.rs", true).unwrap().is_path_pattern());
+    }
+}
@@ -2,6 +2,7 @@
 
 use regex::Regex;
 use std::io;
+use std::path::PathBuf;
 use termion::{color, style};
 
 use crate::app::{AppState, AppStateCmdResult};
@@ -49,6 +50,14 @@ impl AppState for HelpState {
         // can't happen
     }
 
+    fn watched_dirs(&self) -> Vec<PathBuf> {
+        Vec::new()
+    }
+
+    fn refresh(&mut self) {
+        // nothing to refresh
+    }
+
     fn display(&mut self, screen: &mut Screen, con: &AppContext) -> io::Result<()> {
         let mut text = HelpText::new();
         text.md("");
@@ -58,6 +67,13 @@ impl AppState for HelpState {
         text.md("");
         text.md(r#" `<esc>` gets you back to the previous state."#);
         text.md(r#" Typing some letters searches the tree and selects the most relevant file."#);
+        text.md(r#" Starting with a `/` makes it a regular expression, e.g. `/^test_.*\.rs$/i`."#);
+        text.md(r#" Starting with `g/` makes it a glob, e.g. `g/*.test.ts`."#);
+        text.md(r#" Starting with `e/` searches an exact substring, e.g. `e/mod.rs`."#);
+        text.md(r#" Starting with `c/` searches file contents, e.g. `c/TODO`."#);
+        text.md(r#" Starting with `p/` fuzzy searches paths instead of names, e.g. `p/srcmain`."#);
+        text.md(r#" Filters like `>10M`, `mtime<2d`, `type:dir` or `ext:rs` check the metadata of files."#);
+        text.md(r#" Patterns can be combined with `&`, `|`, `!` and parentheses, e.g. `(rs | toml) & !test`."#);
         text.md(r#" Typing a search, a space or `:`, then a verb executes the verb on the file."#);
         text.md("");
         text.md(" Current Verbs:");
@@ -79,10 +95,16 @@ impl AppState for HelpState {
         text.md("  `-h` or `--hidden` : show hidden files");
         text.md("  `-f` or `--only-folders` : only show folders");
         text.md("  `-s` or `--sizes` : display sizes");
+        text.md("  `-d 2` or `--depth 2` : only display the first two levels");
+        text.md("  `-L` or `--follow-links` : explore symlinked directories");
+        text.md("  `-x` or `--one-file-system` : don't explore other file systems");
         text.md("");
         text.md(" Flags are displayed at bottom right:");
         text.md("  `h:y` or `h:n` : whether hidden files are shown");
         text.md("  `gi:a`, `gi:y`, `gi:n` : gitignore on auto, yes or no");
+        text.md("  `c:a`, `c:y`, `c:n` : case sensitivity on auto (smart case), yes or no");
+        text.md("  `t:y` or `t:n` : whether typos are tolerated when there are few matches");
+        text.md("  `d:3` or `d:*` : the maximal depth of the tree, if any");
         text.md("  When gitignore is auto, .gitignore rules are respected if");
         text.md("   the displayed root is a git repository or in one.");
 
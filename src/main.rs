@@ -7,23 +7,34 @@ mod app;
 mod app_context;
 mod browser_states;
 mod commands;
+mod composite_patterns;
 mod conf;
+mod content_patterns;
 mod errors;
+mod exact_patterns;
 mod external;
 mod file_sizes;
+mod filters;
 mod flat_tree;
+mod fuzzy_patterns;
 mod git_ignore;
+mod glob_patterns;
 mod help_states;
 mod input;
+mod normalization;
 mod patterns;
+mod regex_patterns;
 mod screens;
 mod spinner;
 mod status;
 mod task_sync;
+#[cfg(test)]
+mod test_dirs;
 mod tree_build;
 mod tree_options;
 mod tree_views;
 mod verbs;
+mod watcher;
 
 use clap;
 use log::LevelFilter;
@@ -42,7 +53,7 @@ use crate::conf::Conf;
 use crate::errors::ProgramError;
 use crate::external::Launchable;
 use crate::task_sync::TaskLifetime;
-use crate::tree_options::TreeOptions;
+use crate::tree_options::{SortOrder, TreeOptions};
 use crate::verbs::VerbStore;
 
 const VERSION: &str = "0.4.5";
@@ -91,6 +102,25 @@ fn get_cli_args<'a>() -> clap::ArgMatches<'a> {
                 .takes_value(true)
                 .help("respect .gitignore rules (yes, no, auto)"),
         )
+        .arg(
+            clap::Arg::with_name("follow-links")
+                .short("L")
+                .long("follow-links")
+                .help("explore the directories symbolic links point to"),
+        )
+        .arg(
+            clap::Arg::with_name("one-file-system")
+                .short("x")
+                .long("one-file-system")
+                .help("don't explore directories on other file systems"),
+        )
+        .arg(
+            clap::Arg::with_name("depth")
+                .short("d")
+                .long("depth")
+                .takes_value(true)
+                .help("maximal depth of the displayed tree"),
+        )
         .get_matches()
 }
 
@@ -130,6 +160,11 @@ fn run() -> Result<Option<Launchable>, ProgramError> {
     };
     let path = path.canonicalize()?;
     let mut tree_options = TreeOptions::new();
+    tree_options.fuzzy_weights = config.fuzzy_weights;
+    tree_options.sort = config.sort;
+    if config.sort == SortOrder::Size {
+        tree_options.show_sizes = true; // sizes must be computed
+    }
     if cli_args.is_present("only-folders") {
         debug!("show only folders arg set");
         tree_options.only_folders = true;
@@ -146,10 +181,30 @@ fn run() -> Result<Option<Launchable>, ProgramError> {
         debug!("show permissions arg set");
         tree_options.show_permissions = true;
     }
+    if cli_args.is_present("follow-links") {
+        debug!("follow links arg set");
+        tree_options.follow_links = true;
+    }
+    if cli_args.is_present("one-file-system") {
+        debug!("one file system arg set");
+        tree_options.one_filesystem = true;
+    }
     if let Some(respect_ignore) = cli_args.value_of("gitignore") {
         tree_options.respect_git_ignore = respect_ignore.parse()?;
         debug!("respect_git_itnore = {:?}", tree_options.respect_git_ignore);
     }
+    if let Some(depth) = cli_args.value_of("depth") {
+        tree_options.max_depth = match depth.parse() {
+            Ok(depth) if depth > 0 => Some(depth),
+            _ => {
+                return Err(ProgramError::ArgParse {
+                    bad: depth.to_string(),
+                    valid: "a positive integer".to_string(),
+                });
+            }
+        };
+        debug!("max_depth = {:?}", tree_options.max_depth);
+    }
 
     let con = AppContext {
         verb_store,
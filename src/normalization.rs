@@ -0,0 +1,129 @@
+//! normalization of strings before fuzzy and exact matching, so that case
+//!  can be ignored for all letters and not just ASCII ones (É matches é),
+//!  composed and decomposed forms match (é matches e\u{301}) and
+//!  diacritics can be ignored (e matches é).
+//! Chars are decomposed (NFD) and case is folded with the full Unicode
+//!  case folding (ß matches ss), as for a canonical caseless match.
+//! As the normalized string may not have the same number of chars
+//!  than the original one, the index of the original char is kept
+//!  for every normalized char, so that matches can be highlighted.
+
+use std::iter;
+
+use caseless::Caseless;
+use unicode_normalization::UnicodeNormalization;
+
+#[derive(Debug, Clone)]
+pub struct NormalizedChars {
+    pub chars: Vec<char>,
+    pub orig_idx: Vec<usize>, // for each normalized char, the index of the original one
+}
+
+// tell whether the char is a combining diacritical mark
+pub fn is_diacritic(c: char) -> bool {
+    match c as u32 {
+        0x0300..=0x036F => true, // combining diacritical marks
+        0x1AB0..=0x1AFF => true, // combining diacritical marks extended
+        0x1DC0..=0x1DFF => true, // combining diacritical marks supplement
+        0x20D0..=0x20FF => true, // combining diacritical marks for symbols
+        0xFE20..=0xFE2F => true, // combining half marks
+        _ => false,
+    }
+}
+
+impl NormalizedChars {
+    // decompose and, if asked, fold the case and remove the diacritics
+    pub fn from(s: &str, fold_case: bool, keep_diacritics: bool) -> NormalizedChars {
+        let mut chars = Vec::with_capacity(s.len());
+        let mut orig_idx = Vec::with_capacity(s.len());
+        let mut folded = Vec::new();
+        for (idx, c) in s.chars().enumerate() {
+            if c.is_ascii() {
+                chars.push(if fold_case { c.to_ascii_lowercase() } else { c });
+                orig_idx.push(idx);
+                continue;
+            }
+            folded.clear();
+            if fold_case {
+                // folding may produce precomposed chars, hence the second NFD
+                folded.extend(iter::once(c).nfd().default_case_fold().nfd());
+            } else {
+                folded.extend(iter::once(c).nfd());
+            }
+            for &fc in &folded {
+                if keep_diacritics || !is_diacritic(fc) {
+                    chars.push(fc);
+                    orig_idx.push(idx);
+                }
+            }
+        }
+        NormalizedChars { chars, orig_idx }
+    }
+    // convert positions in the normalized chars into positions
+    //  in the original string (removing duplicates)
+    pub fn orig_positions(&self, pos: &[usize]) -> Vec<usize> {
+        let mut orig_pos: Vec<usize> = Vec::with_capacity(pos.len());
+        for &p in pos {
+            let op = self.orig_idx[p];
+            if orig_pos.last() != Some(&op) {
+                orig_pos.push(op);
+            }
+        }
+        orig_pos
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn normalized(s: &str, fold_case: bool, keep_diacritics: bool) -> String {
+        NormalizedChars::from(s, fold_case, keep_diacritics)
+            .chars
+            .iter()
+            .collect()
+    }
+
+    #[test]
+    fn case_is_folded_for_all_letters() {
+        assert_eq!(
+            normalized("ÉTÉ Σ", true, true),
+            normalized("été σ", false, true)
+        );
+        assert_eq!(normalized("Straße", true, true), "strasse");
+        assert_eq!(
+            normalized("ΌΣΟΣ", true, true),
+            normalized("όσος", true, true)
+        );
+        assert_eq!(normalized("İ", true, true), "i\u{307}");
+        assert_eq!(
+            normalized("ÉTÉ", false, true),
+            normalized("E\u{301}TE\u{301}", false, true)
+        );
+    }
+
+    #[test]
+    fn composed_and_decomposed_forms_are_equal() {
+        assert_eq!(normalized("é", false, true), "e\u{301}");
+        assert_eq!(normalized("e\u{301}", false, true), "e\u{301}");
+        // not only latin, greek and cyrillic letters
+        assert_eq!(normalized("한", false, true), "\u{1112}\u{1161}\u{11ab}");
+        assert_eq!(normalized("ダ", false, true), "タ\u{3099}");
+    }
+
+    #[test]
+    fn diacritics_may_be_removed() {
+        assert_eq!(normalized("Crème brûlée", true, false), "creme brulee");
+        assert_eq!(normalized("e\u{301}", false, false), "e");
+    }
+
+    #[test]
+    fn positions_are_mapped_to_the_original_chars() {
+        let nc = NormalizedChars::from("aßé", true, true);
+        assert_eq!(nc.chars, vec!['a', 's', 's', 'e', '\u{301}']);
+        assert_eq!(nc.orig_idx, vec![0, 1, 1, 2, 2]);
+        assert_eq!(nc.orig_positions(&[0, 1, 2, 3]), vec![0, 1, 2]);
+        let nc = NormalizedChars::from("e\u{301}t", true, false);
+        assert_eq!(nc.orig_positions(&[0, 1]), vec![0, 2]);
+    }
+}
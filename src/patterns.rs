@@ -1,92 +1,205 @@
-//! a trivial fuzzy pattern matcher for filename filtering / sorting
-//! It's not meant for file contents but for small strings (less than 1000 chars)
-//!  such as file names.
+//! a pattern is what the user types to filter the tree. It's
+//!  either a fuzzy pattern (the default), a fuzzy pattern applied
+//!  to paths (when the input starts with 'p/'), a regular expression
+//!  (when the input starts with a '/'), a glob (when the input starts
+//!  with 'g/'), a literal substring (when the input starts with 'e/'),
+//!  a search in file contents (when the input starts with 'c/') or a
+//!  filter on metadata (see the filters module)
 
-// weights used in match score computing
-const BONUS_MATCH: i32 = 10_000;
-const BONUS_EXACT: i32 = 1_000;
-const BONUS_START: i32 = 0; // disabled
-const BONUX_CANDIDATE_LENGTH: i32 = -1; // per char
-const BONUS_LENGTH: i32 = -10; // per char of length
-const MAX_LENGTH_BASE: usize = 2;
-const MAX_LENGTH_PER_CHAR: usize = 2;
+use std::fs;
+use std::path::Path;
+
+use crate::content_patterns::ContentPattern;
+use crate::errors::PatternError;
+use crate::exact_patterns::ExactPattern;
+use crate::filters::Filter;
+use crate::fuzzy_patterns::FuzzyPattern;
+use crate::glob_patterns::GlobPattern;
+use crate::regex_patterns::RegexPattern;
+use crate::task_sync::TaskLifetime;
+use crate::tree_options::{OptionBool, TreeOptions};
 
 #[derive(Debug, Clone)]
-pub struct Pattern {
-    lc_chars: Box<[char]>, // lowercase characters
+pub enum Pattern {
+    Fuzzy(FuzzyPattern),
+    PathFuzzy(FuzzyPattern),
+    Regex(RegexPattern),
+    Glob(GlobPattern),
+    Exact(ExactPattern),
+    Content(ContentPattern),
+    Filter(Filter),
 }
 
 /// A Match is a positive result of pattern matching
 #[derive(Debug)]
 pub struct Match {
-    pub score: i32,  // score of the match, guaranteed strictly positive, bigger is better
-    pos: Vec<usize>, // positions of the matching chars
+    pub score: i32, // bigger is better, 0 when the match says nothing about relevance (filters, negations)
+    pub pos: Vec<usize>, // positions of the matching chars
+}
+
+/// What a pattern is tested against
+pub struct Candidate<'a> {
+    pub name: &'a str,                           // the file name
+    pub subpath: &'a str, // the path relative to the tree root (ends with the name)
+    pub path: Option<&'a Path>, // the real path, only when the content may be read
+    pub task_lifetime: Option<&'a TaskLifetime>, // lets the reading of contents be interrupted
+    pub metadata: Option<&'a fs::Metadata>, // not followed if it's a link
 }
 
 impl Pattern {
-    pub fn from(pat: &str) -> Pattern {
-        let lc_chars: Vec<char> = pat.chars().map(|c| c.to_ascii_lowercase()).collect();
-        let lc_chars = lc_chars.into_boxed_slice();
-        Pattern { lc_chars }
-    }
-    fn match_starting_at_index(
-        &self,
-        cand_chars: &[char],
-        start_idx: usize, // start index in candidate
-        max_match_len: usize,
-    ) -> Option<Match> {
-        if cand_chars[start_idx] != self.lc_chars[0] {
-            return None;
-        }
-        let mut pos: Vec<usize> = vec![]; // positions of matching chars in candidate
-        pos.push(start_idx);
-        let mut d = 1;
-        for pat_idx in 1..self.lc_chars.len() {
-            loop {
-                let cand_idx = start_idx + d;
-                if cand_idx == cand_chars.len() || d > max_match_len {
-                    return None;
-                }
-                d += 1;
-                if cand_chars[cand_idx] == self.lc_chars[pat_idx] {
-                    pos.push(cand_idx);
-                    break;
+    // build a pattern from what the user typed:
+    //  - `/some regex/flags` (the closing slash and the flags are optional)
+    //  - `c/some text` for a search in file contents, or `c/"some text"`
+    //    when the text contains spaces, ':' or operators
+    //  - `p/some/path` for a fuzzy search on paths
+    //  - `g/*.rs` for a glob
+    //  - `e/some text` for a literal substring
+    //  - `>10M`, `mtime<2d`, `type:dir`, `ext:rs`, etc. for a filter
+    //  - anything else is a fuzzy pattern
+    // When the case_sensitive option is Auto, the pattern is case sensitive only
+    //  when it contains an uppercase letter ("smart case"), except regular
+    //  expressions which are case sensitive unless they have the 'i' flag.
+    pub fn from(raw: &str, options: &TreeOptions) -> Result<Pattern, PatternError> {
+        let case_sensitive = options.case_sensitive;
+        Ok(if let Some(needle) = raw.strip_prefix("c/") {
+            let needle = unquote(needle);
+            let case_sensitive = is_case_sensitive(case_sensitive, &needle);
+            Pattern::Content(ContentPattern::from(&needle, case_sensitive))
+        } else if let Some(pat) = raw.strip_prefix("p/") {
+            let case_sensitive = is_case_sensitive(case_sensitive, pat);
+            Pattern::PathFuzzy(FuzzyPattern::from(
+                pat,
+                case_sensitive,
+                options.fuzzy_weights,
+            ))
+        } else if let Some(pat) = raw.strip_prefix("g/") {
+            let case_sensitive = is_case_sensitive(case_sensitive, pat);
+            Pattern::Glob(GlobPattern::from(pat, case_sensitive)?)
+        } else if let Some(pat) = raw.strip_prefix("e/") {
+            let case_sensitive = is_case_sensitive(case_sensitive, pat);
+            Pattern::Exact(ExactPattern::from(pat, case_sensitive))
+        } else if let Some(body) = raw.strip_prefix('/') {
+            // the closing slash is the first one which isn't escaped
+            let mut closing_idx = None;
+            let mut escaped = false;
+            for (idx, c) in body.char_indices() {
+                match c {
+                    '\\' => escaped = !escaped,
+                    '/' if !escaped => {
+                        closing_idx = Some(idx);
+                        break;
+                    }
+                    _ => escaped = false,
                 }
             }
+            let (pat, flags) = match closing_idx {
+                Some(idx) => (&body[..idx], &body[idx + 1..]),
+                None => (body, ""),
+            };
+            let case_sensitive = case_sensitive != OptionBool::No;
+            Pattern::Regex(RegexPattern::from(
+                &pat.replace("\\/", "/"),
+                flags,
+                case_sensitive,
+            )?)
+        } else if let Some(filter) = Filter::from(raw) {
+            Pattern::Filter(filter?)
+        } else {
+            let case_sensitive = is_case_sensitive(case_sensitive, raw);
+            Pattern::Fuzzy(FuzzyPattern::from(
+                raw,
+                case_sensitive,
+                options.fuzzy_weights,
+            ))
+        })
+    }
+    // return a match if the pattern matches the candidate.
+    // The positions of the match are the ones of the matching chars in
+    //  the subpath of the candidate, even when only the name is tested.
+    // A content pattern never matches a candidate without path.
+    pub fn test(&self, candidate: &Candidate<'_>) -> Option<Match> {
+        let name_match = match self {
+            Pattern::Fuzzy(fp) => fp.test(candidate.name),
+            Pattern::PathFuzzy(fp) => {
+                return fp.test_path(candidate.subpath);
+            }
+            Pattern::Regex(rp) => rp.test(candidate.name),
+            Pattern::Glob(gp) if gp.is_path_pattern() => {
+                return gp.test(candidate.subpath);
+            }
+            Pattern::Glob(gp) => gp.test(candidate.name),
+            Pattern::Exact(ep) => ep.test(candidate.name),
+            Pattern::Content(cp) => {
+                return candidate
+                    .path
+                    .and_then(|path| cp.test_file(path, candidate.task_lifetime));
+            }
+            Pattern::Filter(f) => {
+                return f.test(candidate);
+            }
+        };
+        name_match.map(|mut m| {
+            let name_start = candidate
+                .subpath
+                .chars()
+                .count()
+                .saturating_sub(candidate.name.chars().count());
+            for p in &mut m.pos {
+                *p += name_start;
+            }
+            m
+        })
+    }
+    // return a copy of the pattern tolerating typos, when
+    //  it's a fuzzy pattern long enough for that
+    pub fn with_typos(&self) -> Option<Pattern> {
+        match self {
+            Pattern::Fuzzy(fp) => fp.with_typos().map(Pattern::Fuzzy),
+            Pattern::PathFuzzy(fp) => fp.with_typos().map(Pattern::PathFuzzy),
+            _ => None,
         }
-        Some(Match { score: 0, pos })
     }
-    // return a match if the pattern can be found in the candidate string
-    pub fn test(&self, candidate: &str) -> Option<Match> {
-        let cand_chars: Vec<char> = candidate.chars().map(|c| c.to_ascii_lowercase()).collect();
-        if cand_chars.len() < self.lc_chars.len() {
-            return None;
+    // tell whether files must be read (with a path in the candidate)
+    //  to know whether they match
+    pub fn is_content_search(&self) -> bool {
+        matches!(self, Pattern::Content(_))
+    }
+    // tell whether the metadata of files are needed to test them
+    pub fn needs_metadata(&self) -> bool {
+        match self {
+            Pattern::Filter(f) => f.needs_metadata(),
+            _ => false,
         }
-        let mut best_score = 0;
-        let max_match_len = MAX_LENGTH_BASE + MAX_LENGTH_PER_CHAR * self.lc_chars.len();
-        let mut best_match: Option<Match> = None;
-        for start_idx in 0..=cand_chars.len() - self.lc_chars.len() {
-            let sm = self.match_starting_at_index(&cand_chars, start_idx, max_match_len);
-            if let Some(mut m) = sm {
-                let match_len = m.pos[m.pos.len() - 1] - m.pos[0];
-                let mut score = BONUS_MATCH;
-                score += BONUX_CANDIDATE_LENGTH * (cand_chars.len() as i32);
-                if m.pos[0] == 0 {
-                    score += BONUS_START;
-                    if cand_chars.len() == self.lc_chars.len() {
-                        score += BONUS_EXACT;
-                    }
-                }
-                score += (match_len as i32) * BONUS_LENGTH;
-                if score > best_score {
-                    best_score = score;
-                    m.score = score;
-                    best_match = Some(m);
-                }
-            }
+    }
+}
+
+fn is_case_sensitive(case_sensitive: OptionBool, pat: &str) -> bool {
+    match case_sensitive {
+        OptionBool::Yes => true,
+        OptionBool::No => false,
+        OptionBool::Auto => pat.chars().any(char::is_uppercase),
+    }
+}
+
+// remove the quotes around a needle typed as `"some text"` (the closing
+//  one may be missing while it's typed) and unescape `\"` and `\\`
+fn unquote(needle: &str) -> String {
+    let body = match needle.strip_prefix('"') {
+        Some(body) => body,
+        None => {
+            return needle.to_string();
+        }
+    };
+    let mut unquoted = String::new();
+    let mut chars = body.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => unquoted.extend(chars.next()),
+            '"' => break,
+            _ => unquoted.push(c),
         }
-        best_match
     }
+    unquoted
 }
 
 impl Match {
@@ -109,3 +222,60 @@ impl Match {
         decorated
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dirs::TestDir;
+
+    #[test]
+    fn quoted_needles_are_unquoted() {
+        assert_eq!(unquote("TODO"), "TODO");
+        assert_eq!(unquote(r#""a: b""#), "a: b");
+        assert_eq!(unquote(r#""say \"hi\" \\o/"#), r#"say "hi" \o/"#);
+    }
+
+    // tell whether the pattern matches a file named "Main.rs", containing
+    //  "fn Main", in the auto, yes and no case sensitivity modes
+    fn matches_in_modes(raw: &str, dir: &TestDir) -> [bool; 3] {
+        let path = dir.file("Main.rs", b"fn Main");
+        let candidate = Candidate {
+            name: "Main.rs",
+            subpath: "Main.rs",
+            path: Some(&path),
+            task_lifetime: None,
+            metadata: None,
+        };
+        let mut matches = [false; 3];
+        for (i, &mode) in [OptionBool::Auto, OptionBool::Yes, OptionBool::No]
+            .iter()
+            .enumerate()
+        {
+            let mut options = TreeOptions::new();
+            options.case_sensitive = mode;
+            let pattern = Pattern::from(raw, &options).unwrap();
+            matches[i] = pattern.test(&candidate).is_some();
+        }
+        matches
+    }
+
+    #[test]
+    fn case_sensitivity_depends_on_the_mode_and_the_kind() {
+        let dir = TestDir::new("patterns-case");
+        for kind in &["", "p/", "e/", "g/", "c/"] {
+            let suffix = if *kind == "g/" { "*" } else { "" };
+            let raw = |pat: &str| format!("{}{}{}", kind, pat, suffix);
+            let m = |pat: &str| matches_in_modes(&raw(pat), &dir);
+            assert_eq!(m("main"), [true, false, true], "kind {:?}", kind);
+            assert_eq!(m("Main"), [true, true, true], "kind {:?}", kind);
+            assert_eq!(m("mAIN"), [false, false, true], "kind {:?}", kind);
+        }
+        // regular expressions are case sensitive unless they have the 'i' flag
+        let m = |pat: &str| matches_in_modes(pat, &dir);
+        assert_eq!(m("/main/"), [false, false, true]);
+        assert_eq!(m("/main"), [false, false, true]);
+        assert_eq!(m("/Main/"), [true, true, true]);
+        assert_eq!(m("/main/i"), [true, true, true]);
+        assert_eq!(m("/mAIN/i"), [true, true, true]);
+    }
+}
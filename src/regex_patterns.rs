@@ -0,0 +1,97 @@
+//! a regular expression based pattern, for when a fuzzy search
+//!  isn't precise enough (e.g. `/^test_.*\.rs$/`)
+
+use regex::{Regex, RegexBuilder};
+
+use crate::errors::PatternError;
+use crate::patterns::Match;
+
+// weights used in match score computing
+const BONUS_MATCH: i32 = 10_000;
+const BONUS_CANDIDATE_LENGTH: i32 = -1; // per char
+
+#[derive(Debug, Clone)]
+pub struct RegexPattern {
+    rex: Regex,
+}
+
+impl RegexPattern {
+    // build a regex pattern from its source and flags (as typed after the closing '/').
+    // The 'i' flag makes the regex case insensitive even if case_sensitive is true.
+    pub fn from(
+        pat: &str,
+        flags: &str,
+        case_sensitive: bool,
+    ) -> Result<RegexPattern, PatternError> {
+        let mut builder = RegexBuilder::new(pat);
+        builder.case_insensitive(!case_sensitive);
+        for c in flags.chars() {
+            match c {
+                'i' => {
+                    builder.case_insensitive(true);
+                }
+                'U' => {
+                    builder.swap_greed(true);
+                }
+                _ => {
+                    return Err(PatternError::UnknownRegexFlag { bad: c });
+                }
+            }
+        }
+        Ok(RegexPattern {
+            rex: builder.build()?,
+        })
+    }
+    // return a match if the regex can be found in the candidate string.
+    // The positions of the match are the ones of the chars of the first
+    //  matching substring.
+    pub fn test(&self, candidate: &str) -> Option<Match> {
+        let rm = self.rex.find(candidate)?;
+        let chars_before = candidate[..rm.start()].chars().count();
+        let nb_matching_chars = rm.as_str().chars().count();
+        let pos = (chars_before..chars_before + nb_matching_chars).collect();
+        let nb_cand_chars = candidate.chars().count() as i32;
+        Some(Match {
+            score: BONUS_MATCH + BONUS_CANDIDATE_LENGTH * nb_cand_chars,
+            pos,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn match_positions_are_char_indexes() {
+        let rp = RegexPattern::from("é.é", "", true).unwrap();
+        let m = rp.test("un été").unwrap();
+        assert_eq!(m.pos, vec![3, 4, 5]);
+        assert!(rp.test("ete").is_none());
+    }
+
+    #[test]
+    fn shorter_candidates_score_better() {
+        let rp = RegexPattern::from("a.c", "", true).unwrap();
+        let short = rp.test("abc").unwrap();
+        let long = rp.test("abcdef").unwrap();
+        assert!(short.score > long.score);
+        assert!(long.score > 0);
+    }
+
+    #[test]
+    fn flags() {
+        let rp = RegexPattern::from("abc", "", true).unwrap();
+        assert!(rp.test("ABC").is_none());
+        let rp = RegexPattern::from("abc", "i", true).unwrap();
+        assert!(rp.test("ABC").is_some());
+        let rp = RegexPattern::from("abc", "", false).unwrap();
+        assert!(rp.test("ABC").is_some());
+        let rp = RegexPattern::from("a.+", "U", true).unwrap();
+        assert_eq!(rp.test("abcd").unwrap().pos, vec![0, 1]);
+        assert!(matches!(
+            RegexPattern::from("abc", "x", true),
+            Err(PatternError::UnknownRegexFlag { bad: 'x' })
+        ));
+    }
+}
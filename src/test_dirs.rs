@@ -0,0 +1,45 @@
+//! temporary directories for the tests needing real files. Each one
+//!  has a unique name, so that tests can run in parallel, and is
+//!  removed when dropped, even when the test panics.
+
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static NB_TEST_DIRS: AtomicUsize = AtomicUsize::new(0);
+
+pub struct TestDir {
+    pub path: PathBuf,
+}
+
+impl TestDir {
+    // create an empty directory in the temp dir of the system
+    pub fn new(name: &str) -> TestDir {
+        let n = NB_TEST_DIRS.fetch_add(1, Ordering::SeqCst);
+        let path = std::env::temp_dir().join(format!("broot-{}-{}-{}", name, process::id(), n));
+        fs::create_dir_all(&path).unwrap();
+        TestDir { path }
+    }
+    // create a directory (and its missing ancestors) in the test dir
+    pub fn dir(&self, subpath: &str) -> PathBuf {
+        let path = self.path.join(subpath);
+        fs::create_dir_all(&path).unwrap();
+        path
+    }
+    // create a file (and its missing ancestors) in the test dir
+    pub fn file(&self, subpath: &str, content: &[u8]) -> PathBuf {
+        let path = self.path.join(subpath);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).unwrap();
+        }
+        fs::write(&path, content).unwrap();
+        path
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        fs::remove_dir_all(&self.path).ok();
+    }
+}
@@ -1,14 +1,27 @@
-use std::cmp::{self, Ordering};
-use std::collections::{BinaryHeap, VecDeque};
+use std::cmp::{self, Ordering, Reverse};
+use std::collections::{BinaryHeap, HashSet, VecDeque};
 use std::fs;
-use std::os::unix::fs::MetadataExt;
-use std::path::PathBuf;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+use std::thread;
 use std::time::{Duration, Instant};
 
-use crate::flat_tree::{LineType, Tree, TreeLine};
+use crate::composite_patterns::CompositePattern;
+use crate::flat_tree::{self, LineType, MatchCount, TargetType, Tree, TreeLine};
 use crate::git_ignore::GitIgnoreFilter;
+use crate::patterns::{Candidate, Match};
 use crate::task_sync::TaskLifetime;
-use crate::tree_options::{OptionBool, TreeOptions};
+use crate::tree_options::{OptionBool, SortOrder, TreeOptions};
+
+// when there are fewer direct matches, and if the option is set, the
+//  search is done again with a pattern tolerating typos
+const MIN_EXACT_MATCHES: usize = 3;
+
+// number of threads reading the directories of a BFS level. Reading
+//  directories is mostly waiting for the disk (or the network), so
+//  there may be more workers than cores
+const NB_READ_WORKERS: usize = 8;
 
 // like a tree line, but with the info needed during the build
 // This structure isn't usable independantly from the tree builder
@@ -22,10 +35,12 @@ struct BLine {
     next_child_idx: usize, // index for iteration, among the children
     line_type: LineType,
     has_error: bool,
-    has_match: bool,
+    has_match: bool, // true when the line or one of its descendants matches the pattern
+    direct_match: bool, // true when the line itself matches the pattern
     score: i32,
+    match_pos: Box<[usize]>, // positions of the matching chars in the subpath
     ignore_filter: Option<GitIgnoreFilter>,
-    nb_kept_children: i32, // used during the trimming step
+    dir_id: Option<(u64, u64)>, // device and inode of directories, only when needed
 }
 
 // the result of trying to build a bline
@@ -67,9 +82,11 @@ impl BLine {
             line_type: LineType::Dir, // it should have been checked before
             has_error: false,         // well... let's hope
             has_match: true,
+            direct_match: false,
             score: 0,
+            match_pos: Box::new([]),
             ignore_filter,
-            nb_kept_children: 0,
+            dir_id: None,
         }
     }
     // return a bline if the direntry directly matches the options and there's no error
@@ -79,59 +96,110 @@ impl BLine {
         depth: u16,
         options: &TreeOptions,
         parent_ignore_filter: &Option<GitIgnoreFilter>,
+        root: &Path,
     ) -> BLineResult {
+        // a name which isn't valid UTF-8 is displayed and searched lossily,
+        //  the exact name is kept in the path
         let name = e.file_name();
-        let name = match name.to_str() {
-            Some(name) => name,
-            None => {
-                return BLineResult::Invalid;
-            }
-        };
+        let name = name.to_string_lossy();
         if !options.show_hidden && name.starts_with('.') {
             return BLineResult::FilteredOutAsHidden;
         }
-        let mut has_match = true;
-        let mut score = 0;
-        if let Some(pattern) = &options.pattern {
-            if let Some(m) = pattern.test(&name) {
-                score = m.score;
-            } else {
-                has_match = false;
-            }
-        }
         let ft = match e.file_type() {
             Ok(ft) => ft,
             Err(_) => {
                 return BLineResult::Invalid;
             }
         };
+        let path = e.path();
+        let mut has_match = true;
+        let mut score = 0;
+        let mut match_pos: Box<[usize]> = Box::new([]);
+        let mut content_search = false; // when true, files are tested later, in gather_lines
+        if let Some(pattern) = &options.pattern {
+            content_search = pattern.has_content_search();
+            if content_search && !ft.is_dir() && !ft.is_symlink() {
+                has_match = false; // not known yet
+            } else {
+                let subpath = path.strip_prefix(root).unwrap_or(&path).to_string_lossy();
+                let metadata = match pattern.needs_metadata() {
+                    true => e.metadata().ok(),
+                    false => None,
+                };
+                let candidate = Candidate {
+                    name: &name,
+                    subpath: &subpath,
+                    path: None,
+                    task_lifetime: None,
+                    metadata: metadata.as_ref(),
+                };
+                if let Some(m) = pattern.test(&candidate) {
+                    score = m.score;
+                    match_pos = m.pos.into_boxed_slice();
+                } else {
+                    has_match = false;
+                }
+            }
+        }
         let mut is_dir = false;
+        let mut dir_id = None;
         let line_type = {
             if ft.is_dir() {
                 is_dir = true;
+                if options.follow_links || options.one_filesystem {
+                    dir_id = e.metadata().ok().map(|md| (md.dev(), md.ino()));
+                }
                 LineType::Dir
             } else if ft.is_symlink() {
-                if !has_match {
-                    return BLineResult::FilteredOutByPattern;
-                }
-                if options.only_folders {
-                    return BLineResult::FilteredOutAsNonFolder;
+                // the metadata of the target, when links are followed
+                let target_md = match options.follow_links {
+                    true => fs::metadata(&path).ok().filter(|md| md.is_dir()),
+                    false => None,
+                };
+                if let Some(md) = target_md {
+                    is_dir = true;
+                    dir_id = Some((md.dev(), md.ino()));
+                    LineType::SymLinkToDir(match fs::read_link(&path) {
+                        Ok(target) => target.to_string_lossy().into_owned(),
+                        Err(_) => String::from("???"),
+                    })
+                } else {
+                    if !has_match {
+                        return BLineResult::FilteredOutByPattern;
+                    }
+                    if options.only_folders {
+                        return BLineResult::FilteredOutAsNonFolder;
+                    }
+                    match fs::read_link(&path) {
+                        Ok(target) => LineType::SymLink(
+                            target.to_string_lossy().into_owned(),
+                            TargetType::of(&path, &target),
+                        ),
+                        Err(_) => LineType::SymLink(String::from("???"), TargetType::Broken),
+                    }
                 }
-                LineType::SymLink(match fs::read_link(&e.path()) {
-                    Ok(target) => target.to_string_lossy().into_owned(),
-                    Err(_) => String::from("???"),
-                })
             } else {
-                if !has_match {
+                if !has_match && !content_search {
                     return BLineResult::FilteredOutByPattern;
                 }
                 if options.only_folders {
                     return BLineResult::FilteredOutAsNonFolder;
                 }
-                LineType::File
+                if ft.is_fifo() {
+                    LineType::Fifo
+                } else if ft.is_socket() {
+                    LineType::Socket
+                } else if ft.is_block_device() {
+                    LineType::BlockDevice
+                } else if ft.is_char_device() {
+                    LineType::CharDevice
+                } else if e.metadata().is_ok_and(|md| md.mode() & 0o111 != 0) {
+                    LineType::Executable
+                } else {
+                    LineType::File
+                }
             }
         };
-        let path = e.path();
         let mut ignore_filter = None;
         if let Some(gif) = parent_ignore_filter {
             if !gif.accepts(&path, &name, is_dir) {
@@ -152,19 +220,26 @@ impl BLine {
             line_type,
             has_error: false,
             has_match,
+            direct_match: has_match && options.pattern.is_some(),
             score,
+            match_pos,
             ignore_filter,
-            nb_kept_children: 0,
+            dir_id,
         })
     }
+    fn is_dir(&self) -> bool {
+        matches!(self.line_type, LineType::Dir | LineType::SymLinkToDir(_))
+    }
     fn to_tree_line(&self) -> TreeLine {
         let mut mode = 0;
         let mut uid = 0;
         let mut gid = 0;
+        let mut mtime = 0;
         if let Ok(metadata) = fs::symlink_metadata(&self.path) {
             mode = metadata.mode();
             uid = metadata.uid();
             gid = metadata.gid();
+            mtime = metadata.mtime();
         }
         TreeLine {
             left_branchs: vec![false; self.depth as usize].into_boxed_slice(),
@@ -175,17 +250,105 @@ impl BLine {
             has_error: self.has_error,
             unlisted: self.children.len() - self.next_child_idx,
             score: self.score,
+            direct_match: self.direct_match,
+            name_match: None,
             mode,
             uid,
             gid,
             size: None,
+            mtime,
         }
     }
 }
 
+// the children of a directory, read but not yet stored in the builder
+struct ReadChildren {
+    children: Vec<BLine>, // sorted
+    nb_gitignored: u32,
+}
+
+// read a directory and build the blines of its children.
+// Returns None if the directory can't be read.
+// This function doesn't need the builder, so that several
+//  directories can be read concurrently.
+fn read_children(
+    parent_idx: usize,
+    parent: &BLine,
+    options: &TreeOptions,
+    root: &Path,
+) -> Option<ReadChildren> {
+    let entries = match fs::read_dir(&parent.path) {
+        Ok(entries) => entries,
+        Err(_err) => {
+            //debug!("Error while listing {:?} : {:?}", parent.path, err);
+            return None;
+        }
+    };
+    let mut children: Vec<BLine> = Vec::new();
+    let mut nb_gitignored = 0;
+    for e in entries {
+        if let Ok(e) = e {
+            let bl = BLine::from(
+                parent_idx,
+                e,
+                parent.depth + 1,
+                options,
+                &parent.ignore_filter,
+                root,
+            );
+            match bl {
+                BLineResult::Some(bl) => {
+                    children.push(bl);
+                }
+                BLineResult::GitIgnored => {
+                    nb_gitignored += 1;
+                }
+                _ => {
+                    // other reason, we don't care
+                }
+            }
+        }
+    }
+    // the sort order decides which children are seen first by the BFS,
+    //  so it's applied now. As the sizes of directories aren't known
+    //  yet, they come first when sorting by size, and are sorted later
+    match options.sort {
+        SortOrder::Name => {
+            children.sort_by_key(|bl| bl.name.to_lowercase());
+        }
+        SortOrder::Size => {
+            children.sort_by_cached_key(|bl| {
+                let size = match bl.is_dir() {
+                    true => 0,
+                    false => fs::metadata(&bl.path).map_or(0, |md| md.len()),
+                };
+                (!bl.is_dir(), Reverse(size), bl.name.to_lowercase())
+            });
+        }
+        SortOrder::Date => {
+            children.sort_by_cached_key(|bl| {
+                let mtime = fs::symlink_metadata(&bl.path).map_or(0, |md| md.mtime());
+                (Reverse(mtime), bl.name.to_lowercase())
+            });
+        }
+        SortOrder::Extension => {
+            children
+                .sort_by_cached_key(|bl| (flat_tree::extension(&bl.name), bl.name.to_lowercase()));
+        }
+        SortOrder::Type => {
+            children.sort_by_cached_key(|bl| (!bl.is_dir(), bl.name.to_lowercase()));
+        }
+    }
+    Some(ReadChildren {
+        children,
+        nb_gitignored,
+    })
+}
+
 // a structure making it possible to keep bline references
 //  sorted in a binary heap with the line with the smallest
-//  score at the top
+//  score at the top (and, among equal scores, the last
+//  gathered one, as it's the deepest)
 struct SortableBLineIdx {
     idx: usize,
     score: i32,
@@ -193,13 +356,13 @@ struct SortableBLineIdx {
 impl Eq for SortableBLineIdx {}
 impl PartialEq for SortableBLineIdx {
     fn eq(&self, other: &SortableBLineIdx) -> bool {
-        self.score == other.score // unused but required by spec of Ord
+        self.cmp(other) == Ordering::Equal // unused but required by spec of Ord
     }
 }
 impl Ord for SortableBLineIdx {
     fn cmp(&self, other: &SortableBLineIdx) -> Ordering {
         if self.score == other.score {
-            Ordering::Equal
+            self.idx.cmp(&other.idx)
         } else if self.score < other.score {
             Ordering::Greater
         } else {
@@ -218,81 +381,168 @@ impl PartialOrd for SortableBLineIdx {
 // All BLines used during build are stored in the blines vector and kept until the end.
 // Most operations and temporary data structures just deal with the indexes of lines in
 //  the blines vector.
+// The exploration may be done in several steps, so that intermediate trees can be
+//  displayed while searching.
 pub struct TreeBuilder {
     blines: Vec<BLine>, // all blines, even the ones not yet "seen" by BFS
     options: TreeOptions,
-    targeted_size: usize, // the number of lines we should fill (height of the screen)
+    targeted_size: usize, // the number of lines we should fill (at least the height of the screen)
     nb_gitignored: u32,   // number of times a gitignore pattern excluded a file
+    approximate: bool,    // true when the pattern was made tolerant to typos
+    total_search: bool,   // true when the whole tree must be searched
+    start: Instant,
+    // state of the BFS, kept between exploration steps
+    out_blines: Vec<usize>, // the blines we want to display (indexes into blines)
+    nb_lines_ok: usize,     // in out_blines
+    open_dirs: VecDeque<usize>,
+    next_level_dirs: Vec<usize>,
+    visited_dirs: HashSet<(u64, u64)>, // device and inode of explored dirs, when following links
+    root_dev: Option<u64>,             // device of the root, when staying on its filesystem
+    expanded_dirs: HashSet<PathBuf>,   // directories whose children must all be listed
+    expanded_idxs: Vec<usize>,         // the blines of the expanded directories
 }
 impl TreeBuilder {
     pub fn from(path: PathBuf, options: TreeOptions, targeted_size: usize) -> TreeBuilder {
-        let mut blines = Vec::new();
-        blines.push(BLine::from_root(path, options.respect_git_ignore));
+        let blines = vec![BLine::from_root(path, options.respect_git_ignore)];
         TreeBuilder {
             blines,
             options,
             targeted_size,
             nb_gitignored: 0,
+            approximate: false,
+            total_search: false,
+            start: Instant::now(),
+            out_blines: Vec::new(),
+            nb_lines_ok: 0,
+            open_dirs: VecDeque::new(),
+            next_level_dirs: Vec::new(),
+            visited_dirs: HashSet::new(),
+            root_dev: None,
+            expanded_dirs: HashSet::new(),
+            expanded_idxs: Vec::new(),
         }
     }
+    // make the children of the given directories all listed, the
+    //  other lines being trimmed to make room if needed
+    pub fn expand(&mut self, dirs: &HashSet<PathBuf>) {
+        self.expanded_dirs = dirs.clone();
+    }
+    // make the next gathering go on until there are nb_lines more lines
+    pub fn extend(&mut self, nb_lines: usize) {
+        self.targeted_size += nb_lines;
+    }
+    // tell whether the exploration reached the end of the tree, so
+    //  that there's no line left to gather
+    pub fn is_exhausted(&self) -> bool {
+        self.open_dirs.is_empty() && self.next_level_dirs.is_empty()
+    }
+    // make the search go on until the whole tree is explored instead
+    //  of stopping when there are enough matches to fill the screen.
+    // The tree then tells how many matches were found.
+    pub fn search_whole_tree(&mut self) {
+        self.total_search = true;
+    }
+    pub fn pattern(&self) -> Option<&CompositePattern> {
+        self.options.pattern.as_ref()
+    }
     // stores (move) the bline in the global vec. Returns its index
     fn store(&mut self, bline: BLine) -> usize {
         let idx = self.blines.len();
         self.blines.push(bline);
         idx
     }
-    // returns true when there are direct matches among children
-    fn load_children(&mut self, bline_idx: usize) -> bool {
+    // stores the children read from a directory.
+    // Returns true when there are direct matches among children
+    fn store_children(&mut self, bline_idx: usize, read: Option<ReadChildren>) -> bool {
         let mut has_child_match = false;
         self.blines[bline_idx].children_loaded = true;
-        match fs::read_dir(&self.blines[bline_idx].path) {
-            Ok(entries) => {
-                let mut children: Vec<usize> = Vec::new();
-                for e in entries {
-                    if let Ok(e) = e {
-                        let bl = BLine::from(
-                            bline_idx,
-                            e,
-                            self.blines[bline_idx].depth + 1,
-                            &self.options,
-                            &self.blines[bline_idx].ignore_filter,
-                        );
-                        match bl {
-                            BLineResult::Some(bl) => {
-                                if bl.has_match {
-                                    // direct match
-                                    self.blines[bline_idx].has_match = true;
-                                    has_child_match = true;
-                                }
-                                children.push(self.store(bl));
-                            }
-                            BLineResult::GitIgnored => {
-                                self.nb_gitignored += 1;
-                            }
-                            _ => {
-                                // other reason, we don't care
-                            }
-                        }
+        match read {
+            Some(read) => {
+                self.nb_gitignored += read.nb_gitignored;
+                for bl in read.children {
+                    if bl.has_match {
+                        // direct match
+                        self.blines[bline_idx].has_match = true;
+                        has_child_match = true;
                     }
+                    let child_idx = self.store(bl);
+                    self.blines[bline_idx].children.push(child_idx);
                 }
-                children.sort_by(|&a, &b| {
-                    self.blines[a]
-                        .name
-                        .to_lowercase()
-                        .cmp(&self.blines[b].name.to_lowercase())
-                });
-                self.blines[bline_idx].children.append(&mut children);
             }
-            Err(_err) => {
-                //debug!(
-                //    "Error while listing {:?} : {:?}",
-                //    self.blines[bline_idx].path, err
-                //);
+            None => {
                 self.blines[bline_idx].has_error = true;
             }
         }
         has_child_match
     }
+    // returns true when there are direct matches among children
+    fn load_children(&mut self, bline_idx: usize) -> bool {
+        let read = read_children(
+            bline_idx,
+            &self.blines[bline_idx],
+            &self.options,
+            &self.blines[0].path,
+        );
+        self.store_children(bline_idx, read)
+    }
+    // read the given directories using a pool of threads, then store
+    //  their children in the same order than if they had been read one
+    //  after the other, so that the result is deterministic.
+    // Returns, for each directory, whether there are direct matches among
+    //  its children, or None if the task expired.
+    fn load_children_of_all(
+        &mut self,
+        bline_idxs: &[usize],
+        task_lifetime: &TaskLifetime,
+    ) -> Option<Vec<bool>> {
+        if bline_idxs.len() < 2 {
+            return Some(
+                bline_idxs
+                    .iter()
+                    .map(|&idx| self.load_children(idx))
+                    .collect(),
+            );
+        }
+        let mut reads: Vec<Option<ReadChildren>> = bline_idxs.iter().map(|_| None).collect();
+        let next = AtomicUsize::new(0); // index in bline_idxs of the next dir to read
+        let blines = &self.blines;
+        let options = &self.options;
+        let root = &self.blines[0].path;
+        thread::scope(|scope| {
+            let workers: Vec<_> = (0..NB_READ_WORKERS.min(bline_idxs.len()))
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut done = Vec::new();
+                        while !task_lifetime.is_expired() {
+                            let i = next.fetch_add(1, AtomicOrdering::Relaxed);
+                            if i >= bline_idxs.len() {
+                                break;
+                            }
+                            let idx = bline_idxs[i];
+                            done.push((i, read_children(idx, &blines[idx], options, root)));
+                        }
+                        done
+                    })
+                })
+                .collect();
+            for worker in workers {
+                for (i, read) in worker.join().unwrap() {
+                    reads[i] = read;
+                }
+            }
+        });
+        if task_lifetime.is_expired() {
+            info!("task expired (directories reading)");
+            return None;
+        }
+        Some(
+            bline_idxs
+                .iter()
+                .zip(reads)
+                .map(|(&idx, read)| self.store_children(idx, read))
+                .collect(),
+        )
+    }
     // load_children must have been called before on bline_idx
     fn next_child(
         &mut self,
@@ -309,24 +559,113 @@ impl TreeBuilder {
         }
     }
 
+    // mark the line and its ancestors as matching.
+    // Returns the number of lines which weren't already matching.
+    fn make_ancestors_match(&mut self, bline_idx: usize) -> usize {
+        let mut nb_new_matches = 0;
+        let mut idx = bline_idx;
+        loop {
+            let bline = &mut self.blines[idx];
+            if !bline.has_match {
+                bline.has_match = true;
+                nb_new_matches += 1;
+            }
+            idx = bline.parent_idx;
+            if idx == 0 {
+                break;
+            }
+        }
+        nb_new_matches
+    }
+
+    // when searching in contents, files aren't tested when their
+    //  bline is built but when they're reached by the BFS. This
+    //  function reads the file and updates the line and its ancestors.
+    // Returns the number of lines which became matching, or None when the
+    //  task expired while reading, in which case nothing was changed.
+    fn test_file_content(
+        &mut self,
+        bline_idx: usize,
+        task_lifetime: &TaskLifetime,
+    ) -> Option<usize> {
+        // special files aren't read, as it could block
+        if !matches!(
+            self.blines[bline_idx].line_type,
+            LineType::File | LineType::Executable
+        ) {
+            return Some(0);
+        }
+        let m = match &self.options.pattern {
+            Some(pattern) if pattern.has_content_search() => {
+                let bline = &self.blines[bline_idx];
+                let subpath = bline
+                    .path
+                    .strip_prefix(&self.blines[0].path)
+                    .unwrap_or(&bline.path)
+                    .to_string_lossy();
+                let metadata = match pattern.needs_metadata() {
+                    true => fs::symlink_metadata(&bline.path).ok(),
+                    false => None,
+                };
+                pattern.test(&Candidate {
+                    name: &bline.name,
+                    subpath: &subpath,
+                    path: Some(&bline.path),
+                    task_lifetime: Some(task_lifetime),
+                    metadata: metadata.as_ref(),
+                })
+            }
+            _ => None,
+        };
+        if task_lifetime.is_expired() {
+            return None;
+        }
+        Some(match m {
+            Some(m) => {
+                self.blines[bline_idx].score = m.score;
+                self.blines[bline_idx].direct_match = true;
+                self.blines[bline_idx].match_pos = m.pos.into_boxed_slice();
+                self.make_ancestors_match(bline_idx)
+            }
+            None => 0,
+        })
+    }
+
     // first step of the build: we explore the directories and gather lines.
     // If there's no search pattern we stop when we have enough lines to fill the screen.
-    // If there's a pattern, we try to gather more lines that will be sorted afterwards.
-    fn gather_lines(&mut self, task_lifetime: &TaskLifetime) -> Option<Vec<usize>> {
-        let start = Instant::now();
-        let mut out_blines: Vec<usize> = Vec::new(); // the blines we want to display (indexes into blines)
+    // If there's a pattern, we try to gather more lines that will be sorted afterwards,
+    //  or all of them in case of a total search.
+    // When a max_duration is given, the exploration is paused after that time and goes
+    //  on at the next call, so that an intermediate tree can be made in between.
+    // Returns true when the gathering is finished, None when the task expired (in
+    //  which case the gathering can still be resumed later).
+    pub fn gather_lines(
+        &mut self,
+        task_lifetime: &TaskLifetime,
+        max_duration: Option<Duration>,
+    ) -> Option<bool> {
+        let step_start = Instant::now();
         let not_long = Duration::from_millis(400);
-        out_blines.push(0);
-        let mut nb_lines_ok = 1; // in out_blines
-        let mut open_dirs: VecDeque<usize> = VecDeque::new();
-        let mut next_level_dirs: Vec<usize> = Vec::new();
-        self.load_children(0);
-        open_dirs.push_back(0);
+        if self.out_blines.is_empty() {
+            self.out_blines.push(0);
+            self.nb_lines_ok = 1;
+            self.load_children(0);
+            self.open_dirs.push_back(0);
+            if let Ok(md) = fs::metadata(&self.blines[0].path) {
+                if self.options.follow_links {
+                    self.visited_dirs.insert((md.dev(), md.ino()));
+                }
+                if self.options.one_filesystem {
+                    self.root_dev = Some(md.dev());
+                }
+            }
+        }
         loop {
             if self.options.pattern.is_some() {
-                if
-                    (nb_lines_ok > 20 * self.targeted_size)
-                    || (nb_lines_ok >= self.targeted_size && start.elapsed() > not_long)
+                if !self.total_search
+                    && ((self.nb_lines_ok > 20 * self.targeted_size)
+                        || (self.nb_lines_ok >= self.targeted_size
+                            && self.start.elapsed() > not_long))
                 {
                     //debug!("break {} {}", nb_lines_ok, 10 * self.targeted_size);
                     break;
@@ -335,57 +674,153 @@ impl TreeBuilder {
                     info!("task expired (core build)");
                     return None;
                 }
-            } else if nb_lines_ok >= self.targeted_size {
+            } else if self.nb_lines_ok >= self.targeted_size {
                 break;
             }
-            if let Some(open_dir_idx) = open_dirs.pop_front() {
+            if let Some(max_duration) = max_duration {
+                if step_start.elapsed() > max_duration {
+                    return Some(false);
+                }
+            }
+            if let Some(open_dir_idx) = self.open_dirs.pop_front() {
                 if let Some(child_idx) = self.next_child(open_dir_idx) {
-                    open_dirs.push_back(open_dir_idx);
-                    let child = &self.blines[child_idx];
-                    if child.has_match {
-                        nb_lines_ok += 1;
-                    }
-                    if child.line_type == LineType::Dir {
-                        next_level_dirs.push(child_idx);
+                    if self.blines[child_idx].has_match {
+                        self.nb_lines_ok += 1;
+                    } else {
+                        match self.test_file_content(child_idx, task_lifetime) {
+                            Some(nb_new_matches) => self.nb_lines_ok += nb_new_matches,
+                            None => {
+                                // the file will be tested again when resuming
+                                self.blines[open_dir_idx].next_child_idx -= 1;
+                                self.open_dirs.push_front(open_dir_idx);
+                                info!("task expired (content search)");
+                                return None;
+                            }
+                        }
                     }
-                    out_blines.push(child_idx);
+                    self.open_dirs.push_back(open_dir_idx);
+                    self.add_out_line(child_idx);
                 }
             } else {
                 // this depth is finished, we must go deeper
-                if next_level_dirs.is_empty() {
+                if self.next_level_dirs.is_empty() {
                     // except there's nothing deeper
                     break;
                 }
-                for next_level_dir_idx in &next_level_dirs {
-                    let has_child_match = self.load_children(*next_level_dir_idx);
+                let next_level_dirs = self.next_level_dirs.clone();
+                // the children of some of them may have been loaded already,
+                //  when a previous tree was made
+                let was_loaded: Vec<bool> = next_level_dirs
+                    .iter()
+                    .map(|&idx| self.blines[idx].children_loaded)
+                    .collect();
+                let to_load: Vec<usize> = next_level_dirs
+                    .iter()
+                    .zip(&was_loaded)
+                    .filter(|(_, &was_loaded)| !was_loaded)
+                    .map(|(&idx, _)| idx)
+                    .collect();
+                let mut has_child_matchs = self
+                    .load_children_of_all(&to_load, task_lifetime)?
+                    .into_iter();
+                for (next_level_dir_idx, was_loaded) in next_level_dirs.iter().zip(was_loaded) {
+                    let has_child_match = match was_loaded {
+                        true => self.blines[*next_level_dir_idx]
+                            .children
+                            .iter()
+                            .any(|&child_idx| self.blines[child_idx].has_match),
+                        false => has_child_matchs.next().unwrap_or(false),
+                    };
                     if has_child_match {
                         // we must ensure the ancestors are made Ok
-                        let mut idx = *next_level_dir_idx;
-                        loop {
-                            let mut bline = &mut self.blines[idx];
-                            if !bline.has_match {
-                                bline.has_match = true;
-                                nb_lines_ok += 1;
-                            }
-                            idx = bline.parent_idx;
-                            if idx == 0 {
-                                break;
-                            }
-                        }
+                        self.nb_lines_ok += self.make_ancestors_match(*next_level_dir_idx);
                     }
-                    open_dirs.push_back(*next_level_dir_idx);
+                    self.open_dirs.push_back(*next_level_dir_idx);
                 }
-                next_level_dirs.clear();
+                self.next_level_dirs.clear();
             }
         }
+        self.list_expanded_dirs(task_lifetime)?;
         if self.options.show_sizes {
             // if the root directory isn't totally read, we finished it even
             // it it goes past the bottom of the screen
             while let Some(child_idx) = self.next_child(0) {
-                out_blines.push(child_idx);
+                self.add_out_line(child_idx);
             }
         }
-        Some(out_blines)
+        Some(true)
+    }
+
+    // tell whether the children of the line would be deeper than the
+    //  max depth, in which case they're never listed
+    fn is_too_deep(&self, bline: &BLine) -> bool {
+        self.options
+            .max_depth
+            .is_some_and(|max_depth| bline.depth >= max_depth)
+    }
+
+    // add a line to the gathered ones and, if it's a directory which
+    //  must be explored, to the ones of the next level
+    fn add_out_line(&mut self, child_idx: usize) {
+        let child = &self.blines[child_idx];
+        let too_deep = self.is_too_deep(child);
+        // when following links, a directory may be reached several
+        //  times (or in a loop) but it's explored only once
+        let visited = match child.dir_id {
+            Some(dir_id) if self.options.follow_links => !self.visited_dirs.insert(dir_id),
+            _ => false,
+        };
+        // a mount point is displayed but not explored
+        let other_fs = match (child.dir_id, self.root_dev) {
+            (Some((dev, _)), Some(root_dev)) => dev != root_dev,
+            _ => false,
+        };
+        if child.is_dir() && !too_deep && !visited && !other_fs {
+            self.next_level_dirs.push(child_idx);
+        }
+        self.out_blines.push(child_idx);
+    }
+
+    // once the exploration is finished, add the remaining children of
+    //  the expanded directories, including the ones which are among the
+    //  added children.
+    // Returns None when the task expired, the listing being then
+    //  resumable.
+    fn list_expanded_dirs(&mut self, task_lifetime: &TaskLifetime) -> Option<()> {
+        if self.expanded_dirs.is_empty() {
+            return Some(());
+        }
+        let mut i = 0;
+        while i < self.out_blines.len() {
+            let idx = self.out_blines[i];
+            i += 1;
+            if !self.expanded_dirs.contains(&self.blines[idx].path)
+                || self.expanded_idxs.contains(&idx)
+                || self.is_too_deep(&self.blines[idx])
+            {
+                continue;
+            }
+            self.expanded_idxs.push(idx);
+            if !self.blines[idx].children_loaded && self.load_children(idx) {
+                self.nb_lines_ok += self.make_ancestors_match(idx);
+            }
+            while let Some(child_idx) = self.next_child(idx) {
+                if self.blines[child_idx].has_match {
+                    self.nb_lines_ok += 1;
+                } else {
+                    match self.test_file_content(child_idx, task_lifetime) {
+                        Some(nb_new_matches) => self.nb_lines_ok += nb_new_matches,
+                        None => {
+                            self.blines[idx].next_child_idx -= 1;
+                            self.expanded_idxs.pop();
+                            return None;
+                        }
+                    }
+                }
+                self.add_out_line(child_idx);
+            }
+        }
+        Some(())
     }
 
     // Post search trimming
@@ -393,23 +828,37 @@ impl TreeBuilder {
     //  strictly necessary to fill the screen.
     // This function keeps only the best ones while taking care of not
     //  removing a parent before its children.
-    fn trim_excess(&mut self, out_blines: &[usize]) {
+    // Returns, for each bline, whether it's kept. The blines aren't modified
+    //  so that the exploration can go on after an intermediate tree is made.
+    fn trim_excess(&self) -> Vec<bool> {
+        let out_blines = &self.out_blines;
+        let mut kept: Vec<bool> = self.blines.iter().map(|bline| bline.has_match).collect();
+        let mut nb_kept_children = vec![0; self.blines.len()];
         let mut count = 1;
         for idx in out_blines[1..].iter() {
-            if self.blines[*idx].has_match {
+            if kept[*idx] {
                 count += 1;
-                let parent_idx = self.blines[*idx].parent_idx;
-                self.blines[parent_idx].nb_kept_children += 1;
+                nb_kept_children[self.blines[*idx].parent_idx] += 1;
+            }
+        }
+        // the expanded directories and their ancestors keep all their
+        //  listed children, so that the expanded ones stay in context
+        let mut keeps_children = vec![false; self.blines.len()];
+        for idx in &self.expanded_idxs {
+            let mut idx = *idx;
+            while !keeps_children[idx] {
+                keeps_children[idx] = true;
+                idx = self.blines[idx].parent_idx;
             }
         }
+        // we keep the complete first level when showing sizes
+        let is_protected = |bline: &BLine| {
+            (bline.depth <= 1 && self.options.show_sizes) || keeps_children[bline.parent_idx]
+        };
         let mut remove_queue: BinaryHeap<SortableBLineIdx> = BinaryHeap::new();
         for idx in out_blines[1..].iter() {
             let bline = &self.blines[*idx];
-            if
-                bline.has_match
-                && bline.nb_kept_children == 0
-                && (bline.depth > 1 || !self.options.show_sizes) // we keep the complete first level when showing sizes
-            {
+            if kept[*idx] && nb_kept_children[*idx] == 0 && !is_protected(bline) {
                 remove_queue.push(SortableBLineIdx {
                     idx: *idx,
                     score: bline.score,
@@ -423,14 +872,13 @@ impl TreeBuilder {
         while count > self.targeted_size {
             if let Some(sli) = remove_queue.pop() {
                 //debug!("removing {:?} with a score of {}", &self.blines[sli.idx].path, self.blines[sli.idx].score);
-                self.blines[sli.idx].has_match = false;
+                kept[sli.idx] = false;
                 let parent_idx = self.blines[sli.idx].parent_idx;
-                let mut parent = &mut self.blines[parent_idx];
-                parent.nb_kept_children -= 1;
-                if parent.nb_kept_children == 0 {
+                nb_kept_children[parent_idx] -= 1;
+                if nb_kept_children[parent_idx] == 0 && !is_protected(&self.blines[parent_idx]) {
                     remove_queue.push(SortableBLineIdx {
                         idx: parent_idx,
-                        score: parent.score,
+                        score: self.blines[parent_idx].score,
                     });
                 }
                 count -= 1;
@@ -439,47 +887,345 @@ impl TreeBuilder {
                 break;
             }
         }
+        kept
     }
 
-    // makes a tree from the builder's specific structure
-    fn into_tree(&mut self, out_blines: &[usize]) -> Tree {
+    // makes a tree from the builder's specific structure, with the kept lines.
+    // When the tree is only an intermediate one, the children of the
+    //  directories not yet explored aren't loaded so they're not counted.
+    fn make_tree(&mut self, kept: &[bool], complete: bool) -> Tree {
         let mut lines: Vec<TreeLine> = Vec::new();
-        for idx in out_blines.iter() {
-            if self.blines[*idx].has_match {
-                // we need to count the children, so we load them
-                if !self.blines[*idx].children_loaded {
-                    if let LineType::Dir = self.blines[*idx].line_type {
-                        self.load_children(*idx);
-                    }
+        let mut line_idxs = vec![None; self.blines.len()]; // bline index -> line index
+        for i in 0..self.out_blines.len() {
+            let idx = self.out_blines[i];
+            if kept[idx] {
+                // we need to count the children, so we load them (but not
+                //  the ones which can't be listed)
+                let bline = &self.blines[idx];
+                if complete && !bline.children_loaded && bline.is_dir() && !self.is_too_deep(bline)
+                {
+                    self.load_children(idx);
                 }
-                lines.push(self.blines[*idx].to_tree_line());
+                line_idxs[idx] = Some(lines.len());
+                lines.push(self.blines[idx].to_tree_line());
             }
         }
+        self.set_name_matches(&line_idxs, &mut lines);
 
+        let lines_len = lines.len();
         let mut tree = Tree {
             lines: lines.into_boxed_slice(),
             selection: 0,
             options: self.options.clone(),
             scroll: 0,
             nb_gitignored: self.nb_gitignored,
+            approximate: self.approximate && lines_len > 1,
+            match_count: None,
         };
+        if self.total_search {
+            tree.match_count = Some(self.match_count(complete));
+        }
         tree.after_lines_changed();
 
-        if self.options.show_sizes {
+        if complete && self.options.show_sizes {
             tree.fetch_file_sizes(); // not the dirs, only simple files
         }
         tree
     }
 
-    // build a tree. Can be called only once per builder
-    pub fn build(mut self, task_lifetime: &TaskLifetime) -> Option<Tree> {
-        debug!("start building with pattern {:?}", self.options.pattern);
-        match self.gather_lines(task_lifetime) {
-            Some(out_blines) => {
-                self.trim_excess(&out_blines);
-                Some(self.into_tree(&out_blines))
+    // compute the chars to highlight in the names of the lines.
+    // The chars of a match may be in the names of the ancestors (in
+    //  a path search): an ancestor gets the chars of its best scoring
+    //  matching descendant.
+    fn set_name_matches(&self, line_idxs: &[Option<usize>], lines: &mut [TreeLine]) {
+        for (idx, bline) in self.blines.iter().enumerate() {
+            if bline.match_pos.is_empty() || line_idxs[idx].is_none() {
+                continue;
+            }
+            // the path from the root, as the positions are in the subpath
+            let mut chain = Vec::new();
+            let mut ancestor_idx = idx;
+            while ancestor_idx != 0 {
+                chain.push(ancestor_idx);
+                ancestor_idx = self.blines[ancestor_idx].parent_idx;
+            }
+            let mut comp_start = 0; // index of the first char of the name in the subpath
+            for &ancestor_idx in chain.iter().rev() {
+                let comp_len = self.blines[ancestor_idx].name.chars().count();
+                let pos: Vec<usize> = bline
+                    .match_pos
+                    .iter()
+                    .filter(|&&p| p >= comp_start && p < comp_start + comp_len)
+                    .map(|p| p - comp_start)
+                    .collect();
+                comp_start += comp_len + 1; // +1 for the separator
+                if pos.is_empty() {
+                    continue;
+                }
+                if let Some(line_idx) = line_idxs[ancestor_idx] {
+                    let line = &mut lines[line_idx];
+                    if line
+                        .name_match
+                        .as_ref()
+                        .is_none_or(|m| m.score < bline.score)
+                    {
+                        line.name_match = Some(Match {
+                            score: bline.score,
+                            pos,
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    // count the direct matches among the gathered lines, and the
+    //  directories containing them
+    fn match_count(&self, complete: bool) -> MatchCount {
+        let mut nb_matches = 0;
+        let mut is_match_parent = vec![false; self.blines.len()];
+        for &idx in &self.out_blines {
+            let bline = &self.blines[idx];
+            if bline.direct_match {
+                nb_matches += 1;
+                is_match_parent[bline.parent_idx] = true;
             }
-            None => None, // interrupted
         }
+        MatchCount {
+            nb_matches,
+            nb_dirs: is_match_parent.iter().filter(|&&b| b).count(),
+            complete,
+        }
+    }
+
+    // return the pattern to use for a new search when there are too
+    //  few direct matches and typos may be tolerated
+    fn typo_tolerant_pattern(&self) -> Option<CompositePattern> {
+        if !self.options.tolerate_typos || self.approximate {
+            return None;
+        }
+        let nb_direct_matches = self
+            .out_blines
+            .iter()
+            .filter(|&&idx| self.blines[idx].direct_match)
+            .count();
+        if nb_direct_matches >= MIN_EXACT_MATCHES {
+            return None;
+        }
+        self.options.pattern.as_ref()?.with_typos()
+    }
+
+    // once the gathering is finished, return the builder to use for a new
+    //  search tolerating typos, if there are too few direct matches
+    pub fn typo_tolerant_builder(&self) -> Option<TreeBuilder> {
+        let pattern = self.typo_tolerant_pattern()?;
+        debug!("few exact matches, searching again with tolerance to typos");
+        let mut options = self.options.clone();
+        options.pattern = Some(pattern);
+        let root = self.blines[0].path.clone();
+        let mut builder = TreeBuilder::from(root, options, self.targeted_size);
+        builder.approximate = true;
+        builder.total_search = self.total_search;
+        Some(builder)
+    }
+
+    // make a tree with the lines gathered until now, which may be
+    //  displayed while the exploration goes on
+    pub fn intermediate_tree(&mut self) -> Tree {
+        let kept = self.trim_excess();
+        self.make_tree(&kept, false)
+    }
+
+    // make the tree, once the gathering is finished
+    pub fn into_tree(mut self) -> Tree {
+        let kept = self.trim_excess();
+        self.make_tree(&kept, true)
+    }
+
+    // build a tree without consuming the builder, so that the exploration
+    //  can go on later, after a call to extend, to make a bigger tree.
+    // Typos aren't tolerated, as it's meant for trees without pattern
+    //  (searches are done step by step with gather_lines)
+    pub fn resumable_build(&mut self, task_lifetime: &TaskLifetime) -> Option<Tree> {
+        self.gather_lines(task_lifetime, None)?;
+        let kept = self.trim_excess();
+        Some(self.make_tree(&kept, true))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::PatternExpr;
+    use crate::test_dirs::TestDir;
+
+    #[test]
+    fn path_matches_are_highlighted_in_ancestors() {
+        let dir = TestDir::new("tree-build-path");
+        dir.file("src/ui/main.rs", b"");
+        let mut options = TreeOptions::new();
+        let expr = PatternExpr::Simple("p/srcmain".to_string());
+        options.pattern = Some(CompositePattern::from(&expr, &options).unwrap());
+        let mut builder = TreeBuilder::from(dir.path.clone(), options, 20);
+        builder.gather_lines(&TaskLifetime::unlimited(), None);
+        let tree = builder.into_tree();
+        let pos = |name: &str| {
+            let line = tree.lines.iter().find(|l| l.name == name).unwrap();
+            line.name_match.as_ref().map(|m| m.pos.clone())
+        };
+        assert_eq!(pos("src"), Some(vec![0, 1, 2]));
+        assert_eq!(pos("ui"), None);
+        assert_eq!(pos("main.rs"), Some(vec![0, 1, 2, 3]));
+    }
+
+    #[test]
+    fn filters_and_negations_match_without_score() {
+        let dir = TestDir::new("tree-build-filter");
+        dir.dir("sub/deeper");
+        dir.file("sub/main.rs", b"");
+        let mut options = TreeOptions::new();
+        let expr = PatternExpr::Or(vec![
+            PatternExpr::Simple("main".to_string()),
+            PatternExpr::Simple("type:dir".to_string()),
+        ]);
+        options.pattern = Some(CompositePattern::from(&expr, &options).unwrap());
+        let mut builder = TreeBuilder::from(dir.path.clone(), options, 20);
+        builder.search_whole_tree();
+        builder.gather_lines(&TaskLifetime::unlimited(), None);
+        let mut tree = builder.into_tree();
+        let line = |name: &str| tree.lines.iter().find(|l| l.name == name).unwrap();
+        assert!(line("sub").direct_match);
+        assert_eq!(line("sub").score, 0);
+        assert!(line("main.rs").score > 0);
+        assert_eq!(tree.match_count.unwrap().nb_matches, 3);
+        tree.try_select_best_match();
+        assert_eq!(tree.selected_line().name, "main.rs");
+    }
+
+    #[test]
+    fn directories_at_max_depth_are_not_listed() {
+        let dir = TestDir::new("tree-build-depth");
+        dir.dir("sub/deeper");
+        dir.file("sub/file", b"");
+        let mut options = TreeOptions::new();
+        options.max_depth = Some(1);
+        let mut builder = TreeBuilder::from(dir.path.clone(), options, 20);
+        builder.expand(&HashSet::from([dir.path.join("sub")]));
+        let tree = builder.resumable_build(&TaskLifetime::unlimited()).unwrap();
+        let names: Vec<&str> = tree.lines[1..].iter().map(|l| l.name.as_str()).collect();
+        assert_eq!(names, vec!["sub"]);
+        assert_eq!(tree.lines[1].unlisted, 0);
+    }
+
+    #[test]
+    fn linked_directories_are_explored_once() {
+        let dir = TestDir::new("tree-build-links");
+        dir.file("a/file", b"");
+        dir.dir("b");
+        std::os::unix::fs::symlink("..", dir.path.join("a/link")).unwrap();
+        std::os::unix::fs::symlink("../a", dir.path.join("b/to_a")).unwrap();
+        let mut options = TreeOptions::new();
+        options.follow_links = true;
+        let mut builder = TreeBuilder::from(dir.path.clone(), options, 100);
+        let tree = builder.resumable_build(&TaskLifetime::unlimited()).unwrap();
+        let mut names: Vec<&str> = tree.lines[1..].iter().map(|l| l.name.as_str()).collect();
+        names.sort_unstable();
+        assert_eq!(names, vec!["a", "b", "file", "link", "to_a"]);
+    }
+
+    #[test]
+    fn expanded_directories_are_fully_listed() {
+        let dir = TestDir::new("tree-build-expand");
+        for i in 0..10 {
+            dir.file(&format!("a/file_{}", i), b"");
+            dir.file(&format!("b/file_{}", i), b"");
+        }
+        let nb_listed = |tree: &Tree, parent: &str| {
+            let parent = dir.path.join(parent);
+            tree.lines
+                .iter()
+                .filter(|l| !l.is_pruning() && l.path.parent() == Some(&parent))
+                .count()
+        };
+        let mut builder = TreeBuilder::from(dir.path.clone(), TreeOptions::new(), 10);
+        let tree = builder.resumable_build(&TaskLifetime::unlimited()).unwrap();
+        assert!(nb_listed(&tree, "a") < 10);
+        let mut builder = TreeBuilder::from(dir.path.clone(), TreeOptions::new(), 10);
+        builder.expand(&HashSet::from([dir.path.join("a")]));
+        let tree = builder.resumable_build(&TaskLifetime::unlimited()).unwrap();
+        assert_eq!(nb_listed(&tree, "a"), 10);
+        assert!(nb_listed(&tree, "b") < 10);
+    }
+
+    #[test]
+    fn more_lines_are_loaded_when_extended() {
+        let dir = TestDir::new("tree-build-extend");
+        for i in 0..30 {
+            dir.file(&format!("sub_{}/file", i), b"");
+        }
+        let mut builder = TreeBuilder::from(dir.path.clone(), TreeOptions::new(), 10);
+        let tree = builder.resumable_build(&TaskLifetime::unlimited()).unwrap();
+        assert!(tree.lines.len() <= 11);
+        assert!(!builder.is_exhausted());
+        builder.extend(100);
+        let tree = builder.resumable_build(&TaskLifetime::unlimited()).unwrap();
+        assert_eq!(tree.lines.len(), 61);
+        assert!(tree.lines.iter().all(|l| !l.is_pruning()));
+        assert!(builder.is_exhausted());
+    }
+
+    #[test]
+    fn links_show_the_type_of_their_target() {
+        let dir = TestDir::new("tree-build-link-types");
+        dir.file("file", b"");
+        dir.dir("sub");
+        for (name, target) in &[
+            ("to_file", "file"),
+            ("to_sub", "sub"),
+            ("to_link", "to_file"),
+            ("to_nothing", "nothing"),
+            ("to_broken_link", "to_nothing"),
+        ] {
+            std::os::unix::fs::symlink(target, dir.path.join(name)).unwrap();
+        }
+        let mut builder = TreeBuilder::from(dir.path.clone(), TreeOptions::new(), 100);
+        let tree = builder.resumable_build(&TaskLifetime::unlimited()).unwrap();
+        let target_type = |name: &str| match &tree
+            .lines
+            .iter()
+            .find(|l| l.name == name)
+            .unwrap()
+            .line_type
+        {
+            LineType::SymLink(_, target_type) => *target_type,
+            line_type => panic!("not a link: {:?}", line_type),
+        };
+        assert_eq!(target_type("to_file"), TargetType::File);
+        assert_eq!(target_type("to_sub"), TargetType::Dir);
+        assert_eq!(target_type("to_link"), TargetType::Link);
+        assert_eq!(target_type("to_nothing"), TargetType::Broken);
+        assert_eq!(target_type("to_broken_link"), TargetType::Broken);
+    }
+
+    #[test]
+    fn names_which_are_not_utf8_are_kept() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+        let dir = TestDir::new("tree-build-not-utf8");
+        let name = OsStr::from_bytes(b"caf\xe9.txt");
+        fs::write(dir.path.join(name), "").unwrap();
+        dir.file("other.txt", b"");
+        let mut options = TreeOptions::new();
+        let expr = PatternExpr::Simple("caf".to_string());
+        options.pattern = Some(CompositePattern::from(&expr, &options).unwrap());
+        let mut builder = TreeBuilder::from(dir.path.clone(), options, 20);
+        builder.gather_lines(&TaskLifetime::unlimited(), None);
+        let tree = builder.into_tree();
+        assert_eq!(tree.lines.len(), 2);
+        let line = &tree.lines[1];
+        assert_eq!(line.path, dir.path.join(name)); // the exact path
+        assert_eq!(line.name, "caf\u{fffd}.txt");
+        assert!(line.has_lossy_name());
+        assert!(!tree.lines[0].has_lossy_name());
     }
 }
@@ -1,5 +1,6 @@
+use crate::composite_patterns::CompositePattern;
 use crate::errors::ProgramError;
-use crate::patterns::Pattern;
+use crate::fuzzy_patterns::FuzzyWeights;
 use std::str::FromStr;
 
 #[derive(Debug, Copy, Clone, PartialEq)]
@@ -24,6 +25,34 @@ impl FromStr for OptionBool {
     }
 }
 
+// the order of the children of a directory. The tree is always
+//  displayed with the children right after their parent.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SortOrder {
+    Name,
+    Size,      // biggest first, once the sizes are computed
+    Date,      // most recently modified first
+    Extension, // then by name
+    Type,      // directories first, then by name
+}
+
+impl FromStr for SortOrder {
+    type Err = ProgramError;
+    fn from_str(s: &str) -> Result<SortOrder, ProgramError> {
+        match s {
+            "name" => Ok(SortOrder::Name),
+            "size" => Ok(SortOrder::Size),
+            "date" => Ok(SortOrder::Date),
+            "extension" => Ok(SortOrder::Extension),
+            "type" => Ok(SortOrder::Type),
+            _ => Err(ProgramError::ArgParse {
+                bad: s.to_string(),
+                valid: "name, size, date, extension, type".to_string(),
+            }),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct TreeOptions {
     pub show_hidden: bool,
@@ -31,7 +60,14 @@ pub struct TreeOptions {
     pub show_sizes: bool,
     pub show_permissions: bool,
     pub respect_git_ignore: OptionBool,
-    pub pattern: Option<Pattern>,
+    pub case_sensitive: OptionBool, // Auto means "smart case"
+    pub fuzzy_weights: FuzzyWeights,
+    pub tolerate_typos: bool, // whether to search again with typos when there are few matches
+    pub max_depth: Option<u16>, // deepest level of displayed lines (None means no limit)
+    pub follow_links: bool,   // whether symlinks to directories are explored
+    pub one_filesystem: bool, // whether mount points are left unexplored
+    pub sort: SortOrder,
+    pub pattern: Option<CompositePattern>,
 }
 
 impl TreeOptions {
@@ -42,6 +78,13 @@ impl TreeOptions {
             show_sizes: false,
             show_permissions: false,
             respect_git_ignore: OptionBool::Auto,
+            case_sensitive: OptionBool::Auto,
+            fuzzy_weights: FuzzyWeights::default(),
+            tolerate_typos: false,
+            max_depth: None,
+            follow_links: false,
+            one_filesystem: false,
+            sort: SortOrder::Name,
             pattern: None,
         }
     }
@@ -52,6 +95,13 @@ impl TreeOptions {
             show_sizes: self.show_sizes,
             show_permissions: self.show_permissions,
             respect_git_ignore: self.respect_git_ignore,
+            case_sensitive: self.case_sensitive,
+            fuzzy_weights: self.fuzzy_weights,
+            tolerate_typos: self.tolerate_typos,
+            max_depth: self.max_depth,
+            follow_links: self.follow_links,
+            one_filesystem: self.one_filesystem,
+            sort: self.sort,
             pattern: None,
         }
     }
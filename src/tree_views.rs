@@ -4,8 +4,9 @@ use std::sync::Mutex;
 use termion::{color, style};
 use users::{Groups, Users, UsersCache};
 
-use crate::flat_tree::{LineType, Tree, TreeLine};
-use crate::patterns::Pattern;
+use crate::composite_patterns::CompositePattern;
+use crate::flat_tree::{LineType, TargetType, Tree, TreeLine};
+use crate::patterns::{Candidate, Match};
 use crate::screens::{Screen, ScreenArea};
 
 pub trait TreeView {
@@ -14,7 +15,7 @@ pub trait TreeView {
         &mut self,
         line: &TreeLine,
         idx: usize,
-        pattern: &Option<Pattern>,
+        pattern: &Option<CompositePattern>,
     ) -> io::Result<()>;
 }
 
@@ -107,7 +108,7 @@ impl TreeView for Screen {
                     }
                 }
                 if tree.options.show_permissions && line_index > 0 {
-                    if line.is_selectable() {
+                    if !line.is_pruning() {
                         write!(
                             self.stdout,
                             "{} {}{}{}{}{}{}{}{}{}",
@@ -207,17 +208,24 @@ impl TreeView for Screen {
         Ok(())
     }
 
+    #[allow(non_upper_case_globals)]
     fn write_line_name(
         &mut self,
         line: &TreeLine,
         idx: usize,
-        pattern: &Option<Pattern>,
+        pattern: &Option<CompositePattern>,
     ) -> io::Result<()> {
         lazy_static! {
             static ref fg_reset: String = format!("{}", color::Fg(color::White)).to_string();
             static ref fg_dir: String = format!("{}", color::Fg(color::LightBlue)).to_string();
             static ref fg_link: String = format!("{}", color::Fg(color::LightMagenta)).to_string();
             static ref fg_match: String = format!("{}", color::Fg(color::Green)).to_string();
+            static ref fg_broken: String = format!("{}", color::Fg(color::Red)).to_string();
+            static ref fg_exe: String = format!("{}", color::Fg(color::LightGreen)).to_string();
+            static ref fg_fifo: String = format!("{}", color::Fg(color::Cyan)).to_string();
+            static ref fg_socket: String = format!("{}", color::Fg(color::LightCyan)).to_string();
+            static ref fg_block: String = format!("{}", color::Fg(color::LightYellow)).to_string();
+            static ref fg_char: String = format!("{}", color::Fg(color::Yellow)).to_string();
             static ref fg_reset_dir: String = format!("{}{}", &*fg_reset, &*fg_dir).to_string();
             static ref fg_reset_link: String = format!("{}{}", &*fg_reset, &*fg_link).to_string();
         }
@@ -238,7 +246,7 @@ impl TreeView for Screen {
                         "{}{}{}",
                         style::Bold,
                         &*fg_dir,
-                        decorated_name(&line.name, pattern, &*fg_match, &*fg_reset_dir),
+                        decorated_name(&line.name, &line.name_match, &*fg_match, &*fg_reset_dir),
                     )?;
                     if line.unlisted > 0 {
                         write!(self.stdout, " …",)?;
@@ -250,19 +258,66 @@ impl TreeView for Screen {
                     self.stdout,
                     "{}{}",
                     &*fg_reset,
-                    decorated_name(&line.name, pattern, &*fg_match, &*fg_reset),
+                    decorated_name(&line.name, &line.name_match, &*fg_match, &*fg_reset),
                 )?;
             }
-            LineType::SymLink(target) => {
+            LineType::Executable
+            | LineType::Fifo
+            | LineType::Socket
+            | LineType::BlockDevice
+            | LineType::CharDevice => {
+                // a color and, like with `ls -F`, a marker after the name
+                let (fg, marker): (&str, &str) = match &line.line_type {
+                    LineType::Executable => (&fg_exe, "*"),
+                    LineType::Fifo => (&fg_fifo, "|"),
+                    LineType::Socket => (&fg_socket, "="),
+                    LineType::BlockDevice => (&fg_block, "#"),
+                    _ => (&fg_char, "%"),
+                };
                 write!(
                     self.stdout,
-                    "{}{} {}->{} {}",
+                    "{}{}{}{}",
+                    fg,
+                    decorated_name(&line.name, &line.name_match, &fg_match, fg),
                     &*fg_reset,
-                    decorated_name(&line.name, pattern, &*fg_match, &*fg_reset),
+                    marker,
+                )?;
+            }
+            LineType::SymLink(target, target_type) => {
+                // a broken link is all red, else the target's color tells its type
+                let (fg_name, fg_target): (&str, &str) = match target_type {
+                    TargetType::File => (&fg_reset, &fg_reset),
+                    TargetType::Dir => (&fg_reset, &fg_dir),
+                    TargetType::Link => (&fg_reset, &fg_link),
+                    TargetType::Broken => (&fg_broken, &fg_broken),
+                };
+                write!(
+                    self.stdout,
+                    "{}{} {}->{} {}",
+                    fg_name,
+                    decorated_name(&line.name, &line.name_match, &fg_match, fg_name),
+                    match target_type {
+                        TargetType::Broken => fg_broken.as_str(),
+                        _ => fg_link.as_str(),
+                    },
+                    fg_target,
+                    decorated_target(&target, pattern, &fg_match, fg_target),
+                )?;
+            }
+            LineType::SymLinkToDir(target) => {
+                write!(
+                    self.stdout,
+                    "{}{}{} {}->{} {}",
+                    style::Bold,
+                    &*fg_dir,
+                    decorated_name(&line.name, &line.name_match, &fg_match, &fg_reset_dir),
                     &*fg_link,
-                    &*fg_reset,
-                    decorated_name(&target, pattern, &*fg_match, &*fg_reset),
+                    &*fg_reset_dir,
+                    decorated_target(target, pattern, &fg_match, &fg_reset_dir),
                 )?;
+                if line.unlisted > 0 {
+                    write!(self.stdout, " …",)?;
+                }
             }
             LineType::Pruning => {
                 write!(
@@ -275,20 +330,41 @@ impl TreeView for Screen {
                 )?;
             }
         }
+        if line.has_lossy_name() {
+            // the displayed name isn't exactly the real one
+            write!(self.stdout, "{} (not UTF-8)", &*fg_broken)?;
+        }
         Ok(())
     }
 }
 
 fn decorated_name<'a>(
     name: &'a str,
-    pattern: &Option<Pattern>,
+    name_match: &Option<Match>,
     prefix: &str,
     postfix: &str,
 ) -> Cow<'a, str> {
-    if let Some(p) = pattern {
-        if let Some(m) = p.test(name) {
-            return Cow::Owned(m.wrap_matching_chars(name, prefix, postfix));
-        }
+    if let Some(m) = name_match {
+        return Cow::Owned(m.wrap_matching_chars(name, prefix, postfix));
     }
     Cow::Borrowed(name)
 }
+
+fn decorated_target<'a>(
+    target: &'a str,
+    pattern: &Option<CompositePattern>,
+    prefix: &str,
+    postfix: &str,
+) -> Cow<'a, str> {
+    let target_match = match pattern {
+        Some(p) => p.test(&Candidate {
+            name: target,
+            subpath: target,
+            path: None,
+            task_lifetime: None,
+            metadata: None,
+        }),
+        None => None,
+    };
+    decorated_name(target, &target_match, prefix, postfix)
+}
@@ -2,8 +2,10 @@ use lazy_static::lazy_static;
 use regex::{Captures, Regex};
 use std::borrow::Cow;
 use std::collections::HashMap;
-use std::fs::OpenOptions;
+use std::ffi::OsString;
+use std::fs::{self, OpenOptions};
 use std::io::{self, Write};
+use std::os::unix::ffi::OsStrExt;
 use std::path::Path;
 
 use crate::app::AppStateCmdResult;
@@ -11,9 +13,15 @@ use crate::app_context::AppContext;
 use crate::browser_states::BrowserState;
 use crate::conf::Conf;
 use crate::external::Launchable;
+use crate::file_sizes::Size;
 use crate::help_states::HelpState;
 use crate::task_sync::TaskLifetime;
-use crate::tree_options::OptionBool;
+use crate::tree_options::{OptionBool, SortOrder, TreeOptions};
+
+lazy_static! {
+    // the arguments to replace in verbs, like `{file}`
+    static ref EXEC_ARG_REGEX: Regex = Regex::new(r"\{([\w.]+)\}").unwrap();
+}
 
 #[derive(Debug, Clone)]
 pub struct Verb {
@@ -26,11 +34,11 @@ pub struct VerbStore {
 }
 
 pub trait VerbExecutor {
-    fn execute_verb(&self, verb: &Verb, con: &AppContext) -> io::Result<AppStateCmdResult>;
+    fn execute_verb(&mut self, verb: &Verb, con: &AppContext) -> io::Result<AppStateCmdResult>;
 }
 
 impl VerbExecutor for HelpState {
-    fn execute_verb(&self, verb: &Verb, _con: &AppContext) -> io::Result<AppStateCmdResult> {
+    fn execute_verb(&mut self, verb: &Verb, _con: &AppContext) -> io::Result<AppStateCmdResult> {
         Ok(match verb.exec_pattern.as_ref() {
             ":open" => AppStateCmdResult::Launch(Launchable::opener(&Conf::default_location())?),
             ":quit" => AppStateCmdResult::Quit,
@@ -40,7 +48,7 @@ impl VerbExecutor for HelpState {
 }
 
 impl VerbExecutor for BrowserState {
-    fn execute_verb(&self, verb: &Verb, con: &AppContext) -> io::Result<AppStateCmdResult> {
+    fn execute_verb(&mut self, verb: &Verb, con: &AppContext) -> io::Result<AppStateCmdResult> {
         let tree = match &self.filtered_tree {
             Some(tree) => &tree,
             None => &self.tree,
@@ -57,14 +65,22 @@ impl VerbExecutor for BrowserState {
                     &TaskLifetime::unlimited(),
                 ))
             }
+            ":total_search" => match &tree.options.pattern {
+                Some(_) => AppStateCmdResult::from_optional_state(BrowserState::new_total_search(
+                    self.tree.root().clone(),
+                    tree.options.clone(),
+                    &TaskLifetime::unlimited(),
+                )),
+                None => AppStateCmdResult::DisplayError(
+                    "type a pattern before asking for a total search".to_string(),
+                ),
+            },
             ":toggle_hidden" => {
                 let mut options = tree.options.clone();
                 options.show_hidden = !options.show_hidden;
-                AppStateCmdResult::from_optional_state(BrowserState::new(
-                    self.tree.root().clone(),
-                    options,
-                    &TaskLifetime::unlimited(),
-                ))
+                AppStateCmdResult::from_optional_state(
+                    self.with_options(options, &TaskLifetime::unlimited()),
+                )
             }
             ":toggle_git_ignore" => {
                 let mut options = tree.options.clone();
@@ -80,54 +96,125 @@ impl VerbExecutor for BrowserState {
                     OptionBool::No => OptionBool::Yes,
                 };
                 debug!("respect_git_ignore = {:?}", options.respect_git_ignore);
-                AppStateCmdResult::from_optional_state(BrowserState::new(
-                    self.tree.root().clone(),
-                    options,
-                    &TaskLifetime::unlimited(),
-                ))
+                AppStateCmdResult::from_optional_state(
+                    self.with_options(options, &TaskLifetime::unlimited()),
+                )
+            }
+            ":toggle_case" => {
+                let mut options = tree.options.clone();
+                options.case_sensitive = match options.case_sensitive {
+                    OptionBool::Auto => OptionBool::Yes,
+                    OptionBool::Yes => OptionBool::No,
+                    OptionBool::No => OptionBool::Auto,
+                };
+                debug!("case_sensitive = {:?}", options.case_sensitive);
+                AppStateCmdResult::from_optional_state(
+                    self.with_options(options, &TaskLifetime::unlimited()),
+                )
+            }
+            ":toggle_typos" => {
+                let mut options = tree.options.clone();
+                options.tolerate_typos = !options.tolerate_typos;
+                AppStateCmdResult::from_optional_state(
+                    self.with_options(options, &TaskLifetime::unlimited()),
+                )
+            }
+            ":depth+" => match tree.options.max_depth {
+                Some(max_depth) => {
+                    let mut options = tree.options.clone();
+                    options.max_depth = Some(max_depth + 1);
+                    AppStateCmdResult::from_optional_state(
+                        self.with_options(options, &TaskLifetime::unlimited()),
+                    )
+                }
+                None => AppStateCmdResult::DisplayError("the depth isn't limited".to_string()),
+            },
+            ":depth-" => {
+                // without limit, we start from the deepest displayed level
+                let max_depth = match tree.options.max_depth {
+                    Some(max_depth) => max_depth,
+                    None => tree.lines.iter().map(|line| line.depth).max().unwrap_or(0),
+                };
+                if max_depth <= 1 {
+                    return Ok(AppStateCmdResult::DisplayError(
+                        "the depth can't be less than 1".to_string(),
+                    ));
+                }
+                let mut options = tree.options.clone();
+                options.max_depth = Some(max_depth - 1);
+                AppStateCmdResult::from_optional_state(
+                    self.with_options(options, &TaskLifetime::unlimited()),
+                )
+            }
+            ":sort_by_name" | ":sort_by_size" | ":sort_by_date" | ":sort_by_extension"
+            | ":sort_by_type" => {
+                let mut options = tree.options.clone();
+                options.sort = match verb.exec_pattern[":sort_by_".len()..].parse() {
+                    Ok(sort) => sort,
+                    Err(e) => {
+                        return Ok(AppStateCmdResult::DisplayError(e.to_string()));
+                    }
+                };
+                if options.sort == SortOrder::Size {
+                    options.show_sizes = true; // sizes must be computed
+                }
+                AppStateCmdResult::from_optional_state(
+                    self.with_options(options, &TaskLifetime::unlimited()),
+                )
             }
             ":toggle_files" => {
                 let mut options = tree.options.clone();
                 options.only_folders = !options.only_folders;
-                AppStateCmdResult::from_optional_state(BrowserState::new(
-                    self.tree.root().clone(),
-                    options,
-                    &TaskLifetime::unlimited(),
-                ))
+                AppStateCmdResult::from_optional_state(
+                    self.with_options(options, &TaskLifetime::unlimited()),
+                )
+            }
+            ":toggle_links" => {
+                let mut options = tree.options.clone();
+                options.follow_links = !options.follow_links;
+                AppStateCmdResult::from_optional_state(
+                    self.with_options(options, &TaskLifetime::unlimited()),
+                )
             }
             ":toggle_perm" => {
                 let mut options = tree.options.clone();
                 options.show_permissions = !options.show_permissions;
-                AppStateCmdResult::from_optional_state(BrowserState::new(
-                    self.tree.root().clone(),
-                    options,
-                    &TaskLifetime::unlimited(),
-                ))
+                AppStateCmdResult::from_optional_state(
+                    self.with_options(options, &TaskLifetime::unlimited()),
+                )
             }
             ":toggle_sizes" => {
                 let mut options = tree.options.clone();
                 options.show_sizes = !options.show_sizes;
-                AppStateCmdResult::from_optional_state(BrowserState::new(
-                    self.tree.root().clone(),
-                    options,
-                    &TaskLifetime::unlimited(),
-                ))
+                AppStateCmdResult::from_optional_state(
+                    self.with_options(options, &TaskLifetime::unlimited()),
+                )
             }
             ":print_path" | ":cd" => {
                 if let Some(ref output_path) = con.output_path {
                     // an output path was provided, we write to it
-                    let f = OpenOptions::new().append(true).open(output_path)?;
-                    writeln!(&f, "{}", path.to_string_lossy())?;
+                    let mut f = OpenOptions::new().append(true).open(output_path)?;
+                    f.write_all(path.as_os_str().as_bytes())?;
+                    writeln!(&f)?;
                     AppStateCmdResult::Quit
                 } else {
                     // no output path provided. We write on stdout, but we must
                     // do it after app closing to have the normal terminal
-                    let mut launchable = Launchable::from(&path.to_string_lossy())?;
+                    let mut launchable = Launchable::from(vec![path.clone().into_os_string()])?;
                     launchable.just_print = true;
                     AppStateCmdResult::Launch(launchable)
                 }
             }
-            ":open" => AppStateCmdResult::Launch(Launchable::opener(path)?),
+            ":open" => match tree.selected_line().special_kind() {
+                Some(kind) => {
+                    AppStateCmdResult::DisplayError(format!("a {} can't be opened", kind))
+                }
+                None => AppStateCmdResult::Launch(Launchable::opener(path)?),
+            },
+            ":jump" => match tree.selected_line().link_target() {
+                Some(target) => jump_to(&target, tree.options.without_pattern()),
+                None => AppStateCmdResult::DisplayError("the selection isn't a link".to_string()),
+            },
             ":parent" => match &self.tree.selected_line().path.parent() {
                 Some(path) => {
                     let path = path.to_path_buf();
@@ -141,17 +228,56 @@ impl VerbExecutor for BrowserState {
                 None => AppStateCmdResult::DisplayError("no parent found".to_string()),
             },
             ":quit" => AppStateCmdResult::Quit,
-            _ => AppStateCmdResult::Launch(Launchable::from(&verb.exec_string(path))?),
+            ":refresh" => {
+                Size::clear_cache(self.tree.root());
+                AppStateCmdResult::RefreshState
+            }
+            ":expand" => match self.expand_selection() {
+                true => AppStateCmdResult::Keep,
+                false => AppStateCmdResult::DisplayError(
+                    "no unlisted file to show in the selection".to_string(),
+                ),
+            },
+            _ => match tree.selected_line().special_kind() {
+                // the launched program would probably read it, and may hang
+                Some(kind) if verb.takes_file() => AppStateCmdResult::DisplayError(format!(
+                    "a {} can't be given to {}",
+                    kind, &verb.name
+                )),
+                _ => AppStateCmdResult::Launch(verb.launchable(path)?),
+            },
         })
     }
 }
 
+// a state displaying the directory of the target of a link, with the
+//  target selected. The path of the directory is canonicalized, so that
+//  it doesn't look like `dir/../other`
+fn jump_to(target: &Path, options: TreeOptions) -> AppStateCmdResult {
+    let dir = match target.parent().map(fs::canonicalize) {
+        Some(Ok(dir)) => dir,
+        _ => {
+            return AppStateCmdResult::DisplayError(format!(
+                "the directory of {:?} doesn't exist",
+                target
+            ));
+        }
+    };
+    let target = match target.file_name() {
+        Some(name) => dir.join(name),
+        None => dir, // the target is `/` or ends with `..`
+    };
+    AppStateCmdResult::from_optional_state(BrowserState::new_selecting(
+        &target,
+        options,
+        &TaskLifetime::unlimited(),
+    ))
+}
+
 impl Verb {
+    // the string describing the command, with the path displayed lossily
     fn exec_string(&self, path: &Path) -> String {
-        lazy_static! {
-            static ref regex: Regex = Regex::new(r"\{([\w.]+)\}").unwrap();
-        }
-        regex
+        EXEC_ARG_REGEX
             .replace_all(&*self.exec_pattern, |caps: &Captures<'_>| {
                 match caps.get(1).unwrap().as_str() {
                     "file" => path.to_string_lossy(),
@@ -160,6 +286,37 @@ impl Verb {
             })
             .to_string()
     }
+    // tell whether the path of the selected file is an argument of the command
+    fn takes_file(&self) -> bool {
+        EXEC_ARG_REGEX
+            .captures_iter(&self.exec_pattern)
+            .any(|caps| &caps[1] == "file")
+    }
+    // the command to launch. The arguments are built from the parts of
+    //  the pattern, so that the path is passed exactly, even when it
+    //  contains spaces or isn't valid UTF-8
+    fn launchable(&self, path: &Path) -> io::Result<Launchable> {
+        let parts = self
+            .exec_pattern
+            .split_whitespace()
+            .map(|token| {
+                let mut part = OsString::new();
+                let mut end = 0;
+                for caps in EXEC_ARG_REGEX.captures_iter(token) {
+                    let m = caps.get(0).unwrap();
+                    part.push(&token[end..m.start()]);
+                    match caps.get(1).unwrap().as_str() {
+                        "file" => part.push(path),
+                        _ => part.push("-hu?-"),
+                    }
+                    end = m.end();
+                }
+                part.push(&token[end..]);
+                part
+            })
+            .collect();
+        Launchable::from(parts)
+    }
     pub fn description_for(&self, state: &BrowserState) -> String {
         let line = match &state.filtered_tree {
             Some(tree) => tree.selected_line(),
@@ -181,12 +338,26 @@ impl Verb {
             ":print_path" => "prints path (e.g. to change directory)".to_string(),
             ":focus" => "displays a directory (mapped to `<enter>`)".to_string(),
             ":open" => "opens a file according to OS settings (mapped to `<enter>`)".to_string(),
+            ":jump" => "goes to the directory of the link's target, and selects it".to_string(),
             ":parent" => "moves to the parent directory".to_string(),
             ":quit" => "quits the application".to_string(),
+            ":refresh" => "reads the tree again, and computes sizes again".to_string(),
+            ":expand" => "lists all the children of the selected directory".to_string(),
+            ":total_search" => "searches the whole tree and counts the matches".to_string(),
             ":toggle_hidden" => "toggles showing hidden files".to_string(),
             ":toggle_git_ignore" => "toggles use of .gitignore".to_string(),
+            ":toggle_case" => "toggles case sensitivity (smart, yes, no)".to_string(),
+            ":toggle_typos" => "toggles tolerance to typos in searches".to_string(),
             ":toggle_files" => "toggles showing files (or just folders)".to_string(),
+            ":toggle_links" => "toggles following symlinks to directories".to_string(),
             ":toggle_sizes" => "toggles showing sizes".to_string(),
+            ":sort_by_name" => "sorts the children of directories by name".to_string(),
+            ":sort_by_size" => "sorts by size, biggest first (and shows sizes)".to_string(),
+            ":sort_by_date" => "sorts by modification date, most recent first".to_string(),
+            ":sort_by_extension" => "sorts by extension, then by name".to_string(),
+            ":sort_by_type" => "sorts directories first, then by name".to_string(),
+            ":depth+" => "shows one more level of the tree".to_string(),
+            ":depth-" => "shows one less level of the tree".to_string(),
             _ => format!("`{}`", self.exec_pattern),
         }
     }
@@ -0,0 +1,154 @@
+//! watching the displayed directories with inotify, so that the
+//!  tree can be refreshed when files are created, deleted or moved.
+//! Watching is only a best effort: a directory which can't be
+//!  watched (e.g. because of the limit on the number of watches)
+//!  just isn't.
+
+use std::collections::HashMap;
+use std::io;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use inotify::{EventMask, Inotify, WatchDescriptor, WatchMask};
+
+// changes made in a burst (e.g. by a build) are reported once, when
+//  no new one came for QUIET_PERIOD, or at most MAX_DELAY after the first one
+const QUIET_PERIOD: Duration = Duration::from_millis(300);
+const MAX_DELAY: Duration = Duration::from_secs(2);
+
+pub struct Watcher {
+    inotify: Inotify, // in non blocking mode
+    watches: HashMap<PathBuf, WatchDescriptor>,
+    buffer: Box<[u8]>, // where events are read
+    debouncer: Debouncer,
+}
+
+// decides when the changes are reported
+#[derive(Default)]
+struct Debouncer {
+    unreported: Option<(Instant, Instant)>, // first and last changes not yet reported
+}
+
+impl Debouncer {
+    // record whether there was a change, and tell whether
+    //  the unreported changes should be reported now
+    fn poll(&mut self, changed: bool, now: Instant) -> bool {
+        if changed {
+            self.unreported = match self.unreported {
+                Some((first, _)) => Some((first, now)),
+                None => Some((now, now)),
+            };
+        }
+        match self.unreported {
+            Some((first, last)) if now - last >= QUIET_PERIOD || now - first >= MAX_DELAY => {
+                self.unreported = None;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Watcher {
+    pub fn new() -> io::Result<Watcher> {
+        Ok(Watcher {
+            inotify: Inotify::init()?,
+            watches: HashMap::new(),
+            buffer: vec![0; 4096].into_boxed_slice(),
+            debouncer: Debouncer::default(),
+        })
+    }
+    // make the watched directories be exactly the given ones
+    pub fn watch(&mut self, dirs: Vec<PathBuf>) {
+        let mut watches = HashMap::new();
+        for dir in dirs {
+            if watches.contains_key(&dir) {
+                continue;
+            }
+            let wd = match self.watches.remove(&dir) {
+                Some(wd) => wd,
+                None => match self.inotify.add_watch(
+                    &dir,
+                    WatchMask::CREATE | WatchMask::DELETE | WatchMask::MOVE,
+                ) {
+                    Ok(wd) => wd,
+                    Err(e) => {
+                        debug!("can't watch {:?} : {:?}", &dir, e);
+                        continue;
+                    }
+                },
+            };
+            watches.insert(dir, wd);
+        }
+        // the remaining ones aren't displayed anymore
+        for (_, wd) in self.watches.drain() {
+            self.inotify.rm_watch(wd).ok(); // the directory may be gone
+        }
+        self.watches = watches;
+    }
+    // tell whether there were changes in the watched directories which
+    //  should now be reported (see QUIET_PERIOD). Never blocks.
+    pub fn has_changes(&mut self) -> bool {
+        let changed = self.read_changes();
+        self.debouncer.poll(changed, Instant::now())
+    }
+    // read the pending events, telling whether there was a change
+    fn read_changes(&mut self) -> bool {
+        let mut changed = false;
+        loop {
+            let events = match self.inotify.read_events(&mut self.buffer) {
+                Ok(events) => events,
+                Err(e) => {
+                    warn!("error while reading inotify events : {:?}", e);
+                    return changed;
+                }
+            };
+            let mut nb_events = 0;
+            for event in events {
+                nb_events += 1;
+                if event.mask.contains(EventMask::IGNORED) {
+                    // the watch was removed, either by us or because
+                    //  the directory was deleted
+                    self.watches.retain(|_, wd| *wd != event.wd);
+                } else {
+                    changed = true;
+                }
+            }
+            if nb_events == 0 {
+                return changed;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changes_are_reported_once_quiet() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut debouncer = Debouncer::default();
+        assert!(!debouncer.poll(false, at(0)));
+        assert!(!debouncer.poll(true, at(0)));
+        assert!(!debouncer.poll(true, at(200)));
+        assert!(!debouncer.poll(false, at(400)));
+        assert!(debouncer.poll(false, at(500)));
+        assert!(!debouncer.poll(false, at(1000)));
+    }
+
+    #[test]
+    fn continuous_changes_are_reported_after_a_max_delay() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut debouncer = Debouncer::default();
+        let mut reports = Vec::new();
+        for ms in (0..=3000).step_by(200) {
+            if debouncer.poll(true, at(ms)) {
+                reports.push(ms);
+            }
+        }
+        assert_eq!(reports, vec![2000]);
+    }
+}